@@ -10,6 +10,7 @@ error SelfCall();
 error SufficientIncome();
 error FlashLenderCall();
 error CoinbaseCall();
+error MissingCheckpoint();
 
 interface IERC20 {
     function balanceOf(address account) external view returns (uint256);
@@ -63,10 +64,39 @@ interface IERC3156FlashLender {
         returns (bool);
 }
 
+interface IUniswapV2Router {
+    function swapExactTokensForETHSupportingFeeOnTransferTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external;
+}
+
+interface IUniswapV3Router {
+    struct ExactInputParams {
+        bytes path;
+        address recipient;
+        uint256 deadline;
+        uint256 amountIn;
+        uint256 amountOutMinimum;
+    }
+
+    function exactInput(ExactInputParams calldata params) external payable returns (uint256 amountOut);
+}
+
+interface IWETH {
+    function withdraw(uint256 wad) external;
+}
+
 contract Arbitrage is Owned, IERC3156FlashBorrower {
     using SafeTransferLib for IERC20;
 
     address flashLender;
+    // The balance before the sandwich, checked after the back leg of the same block.
+    uint256 balanceCheckpoint;
+    uint256 checkpointBlock;
 
     constructor() Owned(msg.sender) {}
 
@@ -88,6 +118,23 @@ contract Arbitrage is Owned, IERC3156FlashBorrower {
         _exec(data);
     }
 
+    // Sandwich front leg, the income is checked by `run_back` over the whole sequence.
+    function run_front(bytes calldata data) external onlyOwner {
+        balanceCheckpoint = address(this).balance;
+        checkpointBlock = block.number;
+        _exec(data);
+    }
+
+    function run_back(bytes calldata data) external onlyOwner {
+        // The front leg is missing or stale, e.g. mined in another block.
+        if (checkpointBlock != block.number) revert MissingCheckpoint();
+        uint256 _balanceCheckpoint = balanceCheckpoint;
+        delete balanceCheckpoint;
+        delete checkpointBlock;
+        _exec(data);
+        if (address(this).balance <= _balanceCheckpoint) revert SufficientIncome();
+    }
+
     function onFlashLoan(address, /* initiator */ address token, uint256 amount, uint256 fee, bytes calldata data)
         public
         returns (bytes32)
@@ -102,6 +149,37 @@ contract Arbitrage is Owned, IERC3156FlashBorrower {
         return keccak256("ERC3156FlashBorrower.onFlashLoan");
     }
 
+    // Swap the token profit to ETH by the uniswap v2 style router, called by the multicall of the contract itself.
+    // The amount in is the balance at execution up to `maxAmountIn`, e.g. less is received of the fee-on-transfer token,
+    // and the min amount out is scaled with it.
+    function swapToNative(address router, address[] calldata path, uint256 maxAmountIn, uint256 amountOutMin)
+        external
+    {
+        if (msg.sender != address(this)) revert SelfCall();
+        uint256 _amountIn = _toAmountIn(path[0], router, maxAmountIn);
+        if (_amountIn == 0) return;
+
+        IUniswapV2Router(router).swapExactTokensForETHSupportingFeeOnTransferTokens(
+            _amountIn, amountOutMin * _amountIn / maxAmountIn, path, address(this), block.timestamp
+        );
+    }
+
+    // The same as `swapToNative` by the uniswap v3 style router, the path ends with WETH, which is unwrapped.
+    function swapToNativeByExactInput(address router, bytes calldata path, uint256 maxAmountIn, uint256 amountOutMin)
+        external
+    {
+        if (msg.sender != address(this)) revert SelfCall();
+        uint256 _amountIn = _toAmountIn(address(bytes20(path[:20])), router, maxAmountIn);
+        if (_amountIn == 0) return;
+
+        uint256 _amountOut = IUniswapV3Router(router).exactInput(
+            IUniswapV3Router.ExactInputParams(
+                path, address(this), block.timestamp, _amountIn, amountOutMin * _amountIn / maxAmountIn
+            )
+        );
+        IWETH(address(bytes20(path[path.length - 20:]))).withdraw(_amountOut);
+    }
+
     function withdraw() external onlyOwner {
         payable(msg.sender).transfer(address(this).balance);
     }
@@ -121,6 +199,14 @@ contract Arbitrage is Owned, IERC3156FlashBorrower {
         if (balance_after <= balance_before) revert SufficientIncome();
     }
 
+    // Approve the router to spend the balance up to `maxAmountIn`.
+    function _toAmountIn(address token, address router, uint256 maxAmountIn) internal returns (uint256) {
+        uint256 _amountIn = IERC20(token).balanceOf(address(this));
+        if (_amountIn > maxAmountIn) _amountIn = maxAmountIn;
+        if (_amountIn > 0) SafeTransferLib.safeApprove(ERC20(token), router, _amountIn);
+        return _amountIn;
+    }
+
     function _exec(bytes calldata data) internal {
         if (data.length > 0) {
             // parse data
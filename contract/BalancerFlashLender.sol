@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+pragma solidity ^0.8.13;
+
+import "solmate/tokens/ERC20.sol";
+import "solmate/utils/SafeTransferLib.sol";
+import {IERC20, IERC3156FlashBorrower, IERC3156FlashLender} from "./Arbitrage.sol";
+
+error VaultCall();
+error UnknownLoan();
+error FlashBorrowerCallback();
+
+interface IFlashLoanRecipient {
+    function receiveFlashLoan(
+        IERC20[] memory tokens,
+        uint256[] memory amounts,
+        uint256[] memory feeAmounts,
+        bytes memory userData
+    ) external;
+}
+
+interface IProtocolFeesCollector {
+    function getFlashLoanFeePercentage() external view returns (uint256);
+}
+
+interface IBalancerVault {
+    function flashLoan(
+        IFlashLoanRecipient recipient,
+        IERC20[] memory tokens,
+        uint256[] memory amounts,
+        bytes memory userData
+    ) external;
+
+    function getProtocolFeesCollector() external view returns (IProtocolFeesCollector);
+}
+
+// ERC-3156 lender of any token held by the Balancer vault, e.g. WETH, USDC and WBTC, see `setFlashLender`.
+// The vault lends without fee unless the governance sets one.
+contract BalancerFlashLender is IERC3156FlashLender, IFlashLoanRecipient {
+    bytes32 constant CALLBACK_SUCCESS = keccak256("ERC3156FlashBorrower.onFlashLoan");
+
+    IBalancerVault public immutable vault;
+    // The user data of the loan in progress, anyone can call the vault with this contract as the recipient.
+    bytes32 pendingLoan;
+
+    constructor(IBalancerVault _vault) {
+        vault = _vault;
+    }
+
+    function maxFlashLoan(address token) public view returns (uint256) {
+        return IERC20(token).balanceOf(address(vault));
+    }
+
+    function flashFee(address, /* token */ uint256 amount) public view returns (uint256) {
+        uint256 _percentage = vault.getProtocolFeesCollector().getFlashLoanFeePercentage();
+        // Rounded up as the vault does, the percentage is in 1e18.
+        return (amount * _percentage + 1e18 - 1) / 1e18;
+    }
+
+    function flashLoan(IERC3156FlashBorrower receiver, address token, uint256 amount, bytes calldata data)
+        external
+        returns (bool)
+    {
+        IERC20[] memory _tokens = new IERC20[](1);
+        _tokens[0] = IERC20(token);
+        uint256[] memory _amounts = new uint256[](1);
+        _amounts[0] = amount;
+        bytes memory _userData = abi.encode(msg.sender, receiver, data);
+
+        pendingLoan = keccak256(_userData);
+        vault.flashLoan(this, _tokens, _amounts, _userData);
+        return true;
+    }
+
+    function receiveFlashLoan(
+        IERC20[] memory tokens,
+        uint256[] memory amounts,
+        uint256[] memory feeAmounts,
+        bytes memory userData
+    ) external {
+        if (msg.sender != address(vault)) revert VaultCall();
+        if (keccak256(userData) != pendingLoan) revert UnknownLoan();
+        delete pendingLoan;
+
+        (address _initiator, IERC3156FlashBorrower _receiver, bytes memory _data) =
+            abi.decode(userData, (address, IERC3156FlashBorrower, bytes));
+        // Safe transfer for the token without the return value, e.g. USDT.
+        ERC20 _token = ERC20(address(tokens[0]));
+        uint256 _amount = amounts[0];
+        uint256 _fee = feeAmounts[0];
+
+        SafeTransferLib.safeTransfer(_token, address(_receiver), _amount);
+        if (_receiver.onFlashLoan(_initiator, address(_token), _amount, _fee, _data) != CALLBACK_SUCCESS) {
+            revert FlashBorrowerCallback();
+        }
+        // The vault checks its balance after the callback.
+        SafeTransferLib.safeTransferFrom(_token, address(_receiver), address(vault), _amount + _fee);
+    }
+}
@@ -1,51 +1,429 @@
 use arbitrage::utils::*;
 use dotenv::dotenv;
 use ethers::prelude::*;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+const RELAY_SIGNER_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+// Simulate again at most so many times once a new block makes the simulation stale.
+const MAX_RESIMULATE: usize = 1;
+const LISTEN_STATS_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    let http_url = get_env("HTTP_RPC_URL");
-    let wss_url = get_env("WSS_RPC_URL");
-    let chain_id = get_env("CHAIN_ID").parse::<u16>().unwrap_or(1);
-    let contract = get_env("CONTRACT").parse::<Address>().unwrap();
-    let private_key = get_env("PRIVATE_KEY").replace("0x", "");
-
-    let provider = Provider::<Http>::connect(&http_url).await;
-    let wallet = private_key
-        .parse::<LocalWallet>()
-        .unwrap()
-        .with_chain_id(chain_id);
-    let flashbot = FlashBotUtil::init(provider, wallet).unwrap();
-
-    let arbitrage = ArbitrageUtil::init(&flashbot, contract);
-    let simulate = Simulate::init(&flashbot, Some(arbitrage.address()))
+    let config = match Config::load(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            process::exit(1);
+        }
+    };
+
+    let provider =
+        Provider::<Http>::try_from(config.http_rpc_url.as_str()).unwrap_or_else(|error| {
+            eprintln!("Invalid config <http_rpc_url>: {error}");
+            process::exit(1);
+        });
+    let relay_signer = RelaySigner::load(&config.relay_signer).unwrap_or_else(|error| {
+        eprintln!("Invalid config <relay_keystore>: {error}");
+        process::exit(1);
+    });
+    if let RelaySignerSource::Random = config.relay_signer {
+        println!("Relay signer is random, set `relay_keystore` to build the reputation");
+    }
+    relay_signer.watch(&config.relay_signer, RELAY_SIGNER_WATCH_INTERVAL, |event| {
+        println!("Relay signer: {event}")
+    });
+    let flashbot = FlashBotUtil::init_with_relay_list(
+        provider,
+        config.wallet.clone(),
+        config.relays.clone(),
+        relay_signer,
+    )
+    .unwrap_or_else(|| {
+        eprintln!("Invalid config <relays>: Expect at least one relay");
+        process::exit(1);
+    })
+    .with_simulation_check(config.simulation_check.clone());
+    let flashbot = match &config.bloxroute_auth {
+        Some(auth) => match BloxrouteRelay::new(BLOXROUTE_URL.parse().unwrap(), auth) {
+            Ok(relay) => flashbot.with_relay(Box::new(relay)),
+            Err(error) => {
+                eprintln!("Invalid config <bloxroute_auth>: {error}");
+                process::exit(1);
+            }
+        },
+        None => flashbot,
+    };
+
+    let flashbot = Arc::new(flashbot);
+    let arbitrage = ArbitrageUtil::init(flashbot.client(), config.contract);
+    let mut simulate = Simulate::init(flashbot.client(), Some(arbitrage.address()))
         .await
-        .unwrap();
-    let listen_poll = ListenPool::init(&wss_url, Some(1)).await;
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to init simulation: {error}");
+            process::exit(1);
+        });
+    if let Some(analysis) = &config.analysis {
+        if let Err(error) = simulate.enable_analysis(analysis) {
+            eprintln!("Invalid config <analysis>: {error}");
+            process::exit(1);
+        }
+    }
+    let simulate = simulate
+        .with_replay_depth_list(config.replay_depth.clone())
+        .with_flash_lender_list(config.flash_lenders.clone());
+    // parity node by default, `geth` or `evm` for the node without `trace_call`
+    let simulate = match config.backend {
+        Backend::Geth => simulate.with_backend(Box::new(
+            GethBackend::init(flashbot.client())
+                .await
+                .unwrap_or_else(|error| {
+                    eprintln!("Failed to init geth backend: {error}");
+                    process::exit(1);
+                }),
+        )),
+        Backend::Evm => simulate.with_backend(Box::new(
+            EvmBackend::init(flashbot.client())
+                .await
+                .unwrap_or_else(|error| {
+                    eprintln!("Failed to init evm backend: {error}");
+                    process::exit(1);
+                }),
+        )),
+        Backend::Parity => simulate,
+    };
+    let backrun = Backrun::init(flashbot.client(), arbitrage.address())
+        .await
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to init backrun: {error}");
+            process::exit(1);
+        });
+    let policy: Arc<dyn BribePolicy> = config.priority.to_policy().into();
+    let listen_poll = config.mempool_sources.iter().fold(
+        ListenPool::init(config.wss_rpc_url.as_str(), config.max_concurrent),
+        |listen_poll, source| listen_poll.with_source(source.as_str()),
+    );
+    let listen_poll = Arc::new(
+        listen_poll
+            .with_queue(TxQueue::new(config.queue_capacity))
+            .with_event_handler(|source, event| println!("Listener {source}: {event}")),
+    );
+    tokio::spawn(print_listen_stats(listen_poll.clone()));
+    let head_tracker = Arc::new(
+        HeadTracker::init(config.wss_rpc_url.as_str())
+            .with_event_handler(|event| println!("Head: {event}")),
+    );
+    tokio::spawn({
+        let head_tracker = head_tracker.clone();
+        async move { head_tracker.run().await }
+    });
+    // The competing bids are the coinbase payments of each new block.
+    if let Priority::Competitive { .. } = config.priority {
+        tokio::spawn(observe_bid(
+            flashbot.clone(),
+            head_tracker.clone(),
+            policy.clone(),
+            config.backend == Backend::Parity,
+        ));
+    }
+    let config = Arc::new(config);
 
     listen_poll
-        .run(|tx_hash| {
-            let simulate = &simulate;
-            let flashbot = &flashbot;
-            let arbitrage = &arbitrage;
-            return async move {
-                let tx_hash = tx_hash.clone();
-                if let Ok(Some((tx_queue, profit))) = simulate.run(tx_hash, false).await {
-                    log_profit(flashbot, arbitrage.address(), tx_hash, profit, || async {
-                        for tx_list in tx_queue {
-                            // Without priority fee, all simulations will fail
-                            if let Ok(tx) = arbitrage.to_tx(tx_list, true, None).await {
-                                let _ = flashbot
-                                    .run(vec![tx])
-                                    .await
-                                    .map(|hash| println!("Transaction hash: {hash:#?}"));
-                            }
-                        }
-                    })
-                    .await;
-                };
-            };
+        .run(|tx| {
+            let tx_hash = tx.hash;
+            let simulate = simulate.clone();
+            let backrun = backrun.clone();
+            let flashbot = flashbot.clone();
+            let arbitrage = arbitrage.clone();
+            let config = config.clone();
+            let policy = policy.clone();
+            let head_tracker = head_tracker.clone();
+            // Each tx runs in its own task, a panic only drops the tx instead of the listener.
+            let task = tokio::spawn(async move {
+                let simulate = &simulate;
+                let backrun = &backrun;
+                let flashbot = flashbot.as_ref();
+                let arbitrage = &arbitrage;
+                let config = config.as_ref();
+                let policy = &policy;
+                let tx = &tx;
+                match config.mode {
+                    Mode::Frontrun => {
+                        let simulation = head_tracker
+                            .run_on_head(
+                                |head| async move { simulate.run_tx_on(tx, &head).await },
+                                MAX_RESIMULATE,
+                            )
+                            .await;
+                        if let Some((head, Ok(Some((tx_queue, profit))))) = simulation {
+                            log_profit(flashbot, arbitrage.address(), tx_hash, profit, || async {
+                                for (index, tx_list) in tx_queue.iter().enumerate() {
+                                    // Without priority fee, all simulations will fail
+                                    // The first attempt is on the simulated head, simulate again on each later block,
+                                    // the tx list of the same replay is sent with the fresh profit.
+                                    let build = |block: U64| {
+                                        let tx_list = tx_list.clone();
+                                        async move {
+                                            let (tx_list, profit) = if block > head.number {
+                                                match simulate.run_tx(tx, false).await? {
+                                                    Some((tx_queue, profit)) => {
+                                                        match tx_queue.into_iter().nth(index) {
+                                                            Some(tx_list) => (tx_list, profit),
+                                                            None => return Ok(None),
+                                                        }
+                                                    }
+                                                    None => return Ok(None),
+                                                }
+                                            } else {
+                                                (tx_list, profit)
+                                            };
+                                            arbitrage
+                                                .to_bribe_bundle(
+                                                    tx_list,
+                                                    config.uncle_protect,
+                                                    profit,
+                                                    policy.as_ref(),
+                                                    flashbot,
+                                                )
+                                                .await
+                                        }
+                                    };
+                                    print_submission(
+                                        flashbot
+                                            .run_until_included(
+                                                build,
+                                                Some(tx_hash),
+                                                config.max_block,
+                                            )
+                                            .await,
+                                    );
+                                }
+                            })
+                            .await;
+                        };
+                    }
+                    Mode::Backrun => {
+                        let (head, opportunity) = match head_tracker
+                            .run_on_head(|_| backrun.run(tx), MAX_RESIMULATE)
+                            .await
+                        {
+                            Some((head, Ok(Some(opportunity)))) => (head, opportunity),
+                            _ => return,
+                        };
+                        let victim = match flashbot.get_raw_transaction(tx_hash).await {
+                            Ok(victim) => victim,
+                            Err(_) => return,
+                        };
+                        log_profit(
+                            flashbot,
+                            arbitrage.address(),
+                            tx_hash,
+                            opportunity.profit,
+                            || async {
+                                // [victim tx, arbitrage tx], the arbitrage tx reverts without the victim tx.
+                                // The first attempt is on the simulated head, search again on each later block.
+                                let build = |block: U64| {
+                                    let victim = victim.clone();
+                                    let opportunity = &opportunity;
+                                    async move {
+                                        let opportunity = if block > head.number {
+                                            match backrun.run(tx).await? {
+                                                Some(opportunity) => opportunity,
+                                                None => return Ok(None),
+                                            }
+                                        } else {
+                                            opportunity.clone()
+                                        };
+                                        let context = BribeContext::init_with_gas_used(
+                                            flashbot,
+                                            opportunity.profit,
+                                            opportunity.gas_used,
+                                        )
+                                        .await?;
+                                        let bundle = match policy.bribe(&context) {
+                                            Some(priority) => Some(vec![
+                                                BundleTx::Signed(victim),
+                                                arbitrage
+                                                    .to_tx(
+                                                        opportunity.tx_list,
+                                                        config.uncle_protect,
+                                                        Some(priority),
+                                                    )
+                                                    .await?
+                                                    .into(),
+                                            ]),
+                                            None => None,
+                                        };
+                                        Ok::<_, ArbitrageError>(bundle)
+                                    }
+                                };
+                                print_submission(
+                                    flashbot
+                                        .run_until_included(build, Some(tx_hash), config.max_block)
+                                        .await,
+                                );
+                            },
+                        )
+                        .await;
+                    }
+                    Mode::Sandwich => {
+                        // The front leg replays the profitable calls of the pending tx, the back leg arbitrages
+                        // the pools it moves, the profit is the sum of both.
+                        let run = |head: Option<Head>| async move {
+                            let front = match head {
+                                Some(head) => simulate.run_tx_on(tx, &head).await?,
+                                None => simulate.run_tx(tx, false).await?,
+                            };
+                            let (front, front_profit) =
+                                match front.and_then(|(tx_queue, profit)| {
+                                    Some((tx_queue.into_iter().next()?, profit))
+                                }) {
+                                    Some(front) => front,
+                                    None => return Ok(None),
+                                };
+                            let back = match backrun.run(tx).await? {
+                                Some(back) => back,
+                                None => return Ok(None),
+                            };
+                            Ok::<_, ArbitrageError>(Some((
+                                front,
+                                back.tx_list,
+                                front_profit + back.profit,
+                            )))
+                        };
+                        let (head, (front, back, profit)) = match head_tracker
+                            .run_on_head(|head| run(Some(head)), MAX_RESIMULATE)
+                            .await
+                        {
+                            Some((head, Ok(Some(sandwich)))) => (head, sandwich),
+                            _ => return,
+                        };
+                        let victim = match flashbot.get_raw_transaction(tx_hash).await {
+                            Ok(victim) => victim,
+                            Err(_) => return,
+                        };
+                        log_profit(flashbot, arbitrage.address(), tx_hash, profit, || async {
+                            // [front tx, victim tx, back tx], the back tx reverts if the whole sequence loses.
+                            // The first attempt is on the simulated head, simulate again on each later block.
+                            let build = |block: U64| {
+                                let victim = victim.clone();
+                                let (front, back) = (front.clone(), back.clone());
+                                async move {
+                                    let (front, back, profit) = if block > head.number {
+                                        match run(None).await? {
+                                            Some(sandwich) => sandwich,
+                                            None => return Ok(None),
+                                        }
+                                    } else {
+                                        (front, back, profit)
+                                    };
+                                    // The whole sequence is simulated by the relay for the gas cost,
+                                    // it fails with `SufficientIncome` if the sandwich loses.
+                                    let bundle = arbitrage
+                                        .to_sandwich_bundle(
+                                            front.clone(),
+                                            victim.clone(),
+                                            back.clone(),
+                                            config.uncle_protect,
+                                            None,
+                                            flashbot,
+                                        )
+                                        .await?;
+                                    let context =
+                                        BribeContext::init(flashbot, profit, bundle).await?;
+                                    match policy.bribe(&context) {
+                                        Some(priority) => Ok(Some(
+                                            arbitrage
+                                                .to_sandwich_bundle(
+                                                    front,
+                                                    victim,
+                                                    back,
+                                                    config.uncle_protect,
+                                                    Some(priority),
+                                                    flashbot,
+                                                )
+                                                .await?,
+                                        )),
+                                        None => Ok(None),
+                                    }
+                                }
+                            };
+                            print_submission(
+                                flashbot
+                                    .run_until_included(build, Some(tx_hash), config.max_block)
+                                    .await,
+                            );
+                        })
+                        .await;
+                    }
+                }
+            });
+            async move {
+                if let Err(error) = task.await {
+                    eprintln!("Task of {tx_hash:?} failed: {error}");
+                }
+            }
         })
         .await;
 }
+
+async fn print_listen_stats(listen_poll: Arc<ListenPool>) {
+    loop {
+        tokio::time::sleep(LISTEN_STATS_INTERVAL).await;
+        println!("Listener queue: {}", listen_poll.queue_stats());
+        for source in listen_poll.source_stats() {
+            println!(
+                "Listener {}: {} first, {} later by {:?} on average",
+                source.url,
+                source.first,
+                source.duplicate,
+                source.mean_lag().unwrap_or_default()
+            );
+        }
+    }
+}
+
+// @param trace Find the bribes paid from the contracts by `trace_block`
+async fn observe_bid(
+    flashbot: Arc<FlashBotUtil>,
+    head_tracker: Arc<HeadTracker>,
+    policy: Arc<dyn BribePolicy>,
+    trace: bool,
+) {
+    let mut head = None;
+    loop {
+        let next = head_tracker.next_head(head.as_ref()).await;
+        match coinbase_payment_list(&flashbot, next.number, flashbot.address(), trace).await {
+            Ok(payment_list) => {
+                if let Some(bid) = payment_list.into_iter().max() {
+                    policy.observe(bid);
+                }
+            }
+            Err(error) => eprintln!("Observe bid of block {} error: {error}", next.number),
+        }
+        head = Some(next);
+    }
+}
+
+fn print_submission(submission: Result<BundleSubmission, ArbitrageError>) {
+    match submission {
+        Ok(submission) => {
+            for attempt in &submission.attempt_list {
+                println!("Block {} simulation {}", attempt.block, attempt.report);
+                for result in &attempt.result_list {
+                    println!("Block {} relay {result}", attempt.block);
+                }
+            }
+            println!("Bundle outcome: {:?}", submission.outcome);
+        }
+        Err(error) if error.is_unprofitable() => println!("Bundle unprofitable: {error}"),
+        Err(error) => println!("Bundle error: {error}"),
+    }
+}
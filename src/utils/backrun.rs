@@ -0,0 +1,487 @@
+use crate::utils::{
+    call_contract, default_factory_list, wrapped_native, ArbitrageError, ERC20Token,
+    IUniswapV2Factory,
+};
+use cfmms::pool::UniswapV2Pool;
+use ethers::{abi::AbiDecode, prelude::*};
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// The gas of the arbitrage tx, the tx can't be estimated before the victim tx is mined.
+const BASE_GAS: u64 = 80_000;
+const GAS_PER_HOP: u64 = 100_000;
+// The longest cycle from the wrapped native token.
+const MAX_HOP: usize = 3;
+
+abigen!(
+    IUniswapV2SwapRouter,
+    r#"[
+        function factory() external view returns (address)
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactETHForTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable returns (uint256[] memory amounts)
+        function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+    ]"#;
+
+    IUniswapV2Pair,
+    r#"[
+        function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data) external
+    ]"#;
+
+    IWrappedNativeToken,
+    r#"[
+        function deposit() external payable
+        function withdraw(uint256 wad) external
+    ]"#;
+);
+
+// The exact input swap of uniswap v2 style router in the mempool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSwap {
+    pub router: Address,
+    pub path: Vec<Address>,
+    pub amount_in: U256,
+}
+
+// One swap of the cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackrunOpportunity {
+    // The calls of the arbitrage contract, see `ArbitrageUtil::to_tx`.
+    pub tx_list: Vec<TransactionRequest>,
+    pub route: Vec<Hop>,
+    pub amount_in: U256,
+    pub profit: U256,
+    pub gas_used: U256,
+}
+
+// Decode the exact input swap, the output amount doesn't matter to move the price.
+// @return None if the tx isn't a swap of uniswap v2 style router
+pub fn decode_swap(tx: &Transaction) -> Option<PendingSwap> {
+    let router = tx.to?;
+    let (path, amount_in) = match IUniswapV2SwapRouterCalls::decode(&tx.input).ok()? {
+        IUniswapV2SwapRouterCalls::SwapExactTokensForTokens(call) => (call.path, call.amount_in),
+        IUniswapV2SwapRouterCalls::SwapExactETHForTokens(call) => (call.path, tx.value),
+        IUniswapV2SwapRouterCalls::SwapExactTokensForETH(call) => (call.path, call.amount_in),
+        IUniswapV2SwapRouterCalls::SwapExactTokensForTokensSupportingFeeOnTransferTokens(call) => {
+            (call.path, call.amount_in)
+        }
+        IUniswapV2SwapRouterCalls::SwapExactETHForTokensSupportingFeeOnTransferTokens(call) => {
+            (call.path, tx.value)
+        }
+        IUniswapV2SwapRouterCalls::SwapExactTokensForETHSupportingFeeOnTransferTokens(call) => {
+            (call.path, call.amount_in)
+        }
+        IUniswapV2SwapRouterCalls::Factory(_) => return None,
+    };
+    (path.len() >= 2 && !amount_in.is_zero()).then_some(PendingSwap {
+        router,
+        path,
+        amount_in,
+    })
+}
+
+// Backrun the pending swap: the swap moves the price of the pools on its path,
+// then the cycle from the wrapped native token through the moved pools may be profitable.
+pub struct Backrun<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+    contract: Address,
+    wrapped_native: Option<Address>,
+    v2_factory_list: Vec<Address>,
+}
+
+// Not derived, the middleware needn't be `Clone` to share the client.
+impl<M, S> Clone for Backrun<M, S> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            contract: self.contract,
+            wrapped_native: self.wrapped_native,
+            v2_factory_list: self.v2_factory_list.clone(),
+        }
+    }
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> Backrun<M, S> {
+    pub async fn init(
+        client: Arc<SignerMiddleware<M, S>>,
+        contract: Address,
+    ) -> Result<Self, ArbitrageError> {
+        let chain_id = client.get_chainid().await?.as_u64();
+        Ok(Self {
+            client,
+            contract,
+            wrapped_native: wrapped_native(chain_id),
+            v2_factory_list: default_factory_list(chain_id).0,
+        })
+    }
+
+    // @return The most profitable cycle after the victim tx, none if the tx isn't a swap or no cycle is profitable
+    pub async fn run(
+        &self,
+        tx: &Transaction,
+    ) -> Result<Option<BackrunOpportunity>, ArbitrageError> {
+        let (swap, wrapped_native) = match (decode_swap(tx), self.wrapped_native) {
+            (Some(swap), Some(wrapped_native)) => (swap, wrapped_native),
+            _ => return Ok(None),
+        };
+        let client = self.client.clone();
+        // Not a router, e.g. the same selector of another contract.
+        let factory = match call_contract(
+            &self.client,
+            IUniswapV2SwapRouter::new(swap.router, client.clone()).factory(),
+        )
+        .await
+        {
+            Ok(factory) => factory,
+            Err(_) => return Ok(None),
+        };
+
+        // The pools of the victim path, and the pools to close the cycle on all factories.
+        let mut factory_list = vec![factory];
+        factory_list.extend(
+            self.v2_factory_list
+                .iter()
+                .filter(|other| **other != factory),
+        );
+        // The victim path on the router factory goes first.
+        let mut pair_list = Vec::new();
+        for factory in &factory_list {
+            for pair in swap.path.windows(2) {
+                pair_list.push((*factory, pair[0], pair[1]));
+            }
+            for token in swap.path.iter().filter(|token| **token != wrapped_native) {
+                pair_list.push((*factory, *token, wrapped_native));
+            }
+        }
+        let address_list = join_all(pair_list.iter().map(|(factory, token_0, token_1)| {
+            let factory = IUniswapV2Factory::new(*factory, client.clone());
+            async move {
+                call_contract(&self.client, factory.get_pair(*token_0, *token_1))
+                    .await
+                    .ok()
+            }
+        }))
+        .await;
+
+        // The victim path must exist on its factory, or the swap reverts.
+        let hop_count = swap.path.len() - 1;
+        let mut affected_list = Vec::new();
+        for address in &address_list[..hop_count] {
+            match address {
+                Some(address) if !address.is_zero() => affected_list.push(*address),
+                _ => return Ok(None),
+            }
+        }
+        let mut unique_list = address_list
+            .into_iter()
+            .flatten()
+            .filter(|address| !address.is_zero())
+            .collect::<Vec<_>>();
+        unique_list.sort();
+        unique_list.dedup();
+
+        let pool_list = join_all(
+            unique_list
+                .iter()
+                .map(|address| UniswapV2Pool::new_from_address(*address, client.clone())),
+        )
+        .await;
+        let mut pool_map = HashMap::new();
+        for pool in pool_list {
+            let pool = pool?;
+            if pool.reserve_0 > 0 && pool.reserve_1 > 0 {
+                pool_map.insert(pool.address, pool);
+            }
+        }
+
+        // Move the price by the victim swap.
+        let mut amount = swap.amount_in;
+        for (token_in, address) in swap.path.iter().zip(&affected_list) {
+            let pool = match pool_map.get_mut(address) {
+                Some(pool) => pool,
+                None => return Ok(None),
+            };
+            amount = pool.simulate_swap_mut(*token_in, amount);
+        }
+
+        let balance = self.client.get_balance(self.contract, None).await?;
+        let best = find_route_list(&pool_map, wrapped_native, &affected_list)
+            .into_iter()
+            .map(|route| {
+                let amount_in = best_amount_in(&pool_map, &route, balance);
+                let amount_out = simulate_route(&pool_map, &route, amount_in);
+                (route, amount_in, amount_out)
+            })
+            .filter(|(_, amount_in, amount_out)| amount_out > amount_in)
+            .max_by_key(|(_, amount_in, amount_out)| *amount_out - *amount_in);
+
+        Ok(
+            best.map(|(route, amount_in, amount_out)| BackrunOpportunity {
+                tx_list: self.to_tx_list(&pool_map, &route, wrapped_native, amount_in),
+                gas_used: U256::from(BASE_GAS + GAS_PER_HOP * route.len() as u64),
+                profit: amount_out - amount_in,
+                route,
+                amount_in,
+            }),
+        )
+    }
+
+    // Wrap the input, send it to the first pool, each pool sends the output to the next one,
+    // and the last pool sends it back to the contract to unwrap.
+    fn to_tx_list(
+        &self,
+        pool_map: &HashMap<Address, UniswapV2Pool>,
+        route: &[Hop],
+        wrapped_native: Address,
+        amount_in: U256,
+    ) -> Vec<TransactionRequest> {
+        let client = self.client.clone();
+        let wrapped_native_token = IWrappedNativeToken::new(wrapped_native, client.clone());
+        let mut tx_list = vec![
+            to_tx(
+                wrapped_native,
+                amount_in,
+                wrapped_native_token.deposit().calldata(),
+            ),
+            to_tx(
+                wrapped_native,
+                U256::zero(),
+                ERC20Token::new(wrapped_native, client.clone())
+                    .transfer(route[0].pool, amount_in)
+                    .calldata(),
+            ),
+        ];
+
+        let mut amount = amount_in;
+        for (index, hop) in route.iter().enumerate() {
+            let pool = &pool_map[&hop.pool];
+            amount = pool.simulate_swap(hop.token_in, amount);
+            let (amount_0_out, amount_1_out) = match hop.token_out == pool.token_a {
+                true => (amount, U256::zero()),
+                false => (U256::zero(), amount),
+            };
+            let recipient = route
+                .get(index + 1)
+                .map(|hop| hop.pool)
+                .unwrap_or(self.contract);
+            tx_list.push(to_tx(
+                hop.pool,
+                U256::zero(),
+                IUniswapV2Pair::new(hop.pool, client.clone())
+                    .swap(amount_0_out, amount_1_out, recipient, Bytes::default())
+                    .calldata(),
+            ));
+        }
+        tx_list.push(to_tx(
+            wrapped_native,
+            U256::zero(),
+            wrapped_native_token.withdraw(amount).calldata(),
+        ));
+
+        tx_list
+    }
+}
+
+fn to_tx(to: Address, value: U256, data: Option<Bytes>) -> TransactionRequest {
+    TransactionRequest {
+        to: Some(to.into()),
+        value: Some(value),
+        data,
+        ..Default::default()
+    }
+}
+
+fn other_token(pool: &UniswapV2Pool, token: Address) -> Option<Address> {
+    match token {
+        token if token == pool.token_a => Some(pool.token_b),
+        token if token == pool.token_b => Some(pool.token_a),
+        _ => None,
+    }
+}
+
+// The cycles from the wrapped native token back to itself in at most `MAX_HOP` pools,
+// each cycle passes at least one pool moved by the victim swap.
+pub fn find_route_list(
+    pool_map: &HashMap<Address, UniswapV2Pool>,
+    wrapped_native: Address,
+    affected_list: &[Address],
+) -> Vec<Vec<Hop>> {
+    fn search(
+        pool_map: &HashMap<Address, UniswapV2Pool>,
+        wrapped_native: Address,
+        route: &mut Vec<Hop>,
+        route_list: &mut Vec<Vec<Hop>>,
+    ) {
+        let token_in = route
+            .last()
+            .map(|hop| hop.token_out)
+            .unwrap_or(wrapped_native);
+        for pool in pool_map.values() {
+            if route.iter().any(|hop| hop.pool == pool.address) {
+                continue;
+            }
+            let token_out = match other_token(pool, token_in) {
+                Some(token_out) => token_out,
+                None => continue,
+            };
+            route.push(Hop {
+                pool: pool.address,
+                token_in,
+                token_out,
+            });
+            if token_out == wrapped_native {
+                if route.len() > 1 {
+                    route_list.push(route.clone());
+                }
+            } else if route.len() < MAX_HOP {
+                search(pool_map, wrapped_native, route, route_list);
+            }
+            route.pop();
+        }
+    }
+
+    let mut route_list = Vec::new();
+    search(pool_map, wrapped_native, &mut Vec::new(), &mut route_list);
+    route_list.retain(|route| route.iter().any(|hop| affected_list.contains(&hop.pool)));
+    route_list
+}
+
+pub fn simulate_route(
+    pool_map: &HashMap<Address, UniswapV2Pool>,
+    route: &[Hop],
+    amount_in: U256,
+) -> U256 {
+    route.iter().fold(amount_in, |amount, hop| {
+        pool_map[&hop.pool].simulate_swap(hop.token_in, amount)
+    })
+}
+
+// The profit of the constant product cycle is concave in the input, ternary search in [0, max_amount_in].
+pub fn best_amount_in(
+    pool_map: &HashMap<Address, UniswapV2Pool>,
+    route: &[Hop],
+    max_amount_in: U256,
+) -> U256 {
+    let (mut low, mut high) = (U256::zero(), max_amount_in);
+    while high - low > U256::from(2) {
+        let mid_low = low + (high - low) / 3;
+        let mid_high = high - (high - low) / 3;
+        // profit(mid_low) < profit(mid_high) without the negative profit
+        if simulate_route(pool_map, route, mid_low) + mid_high
+            < simulate_route(pool_map, route, mid_high) + mid_low
+        {
+            low = mid_low;
+        } else {
+            high = mid_high;
+        }
+    }
+    // The smallest input of the best profit, zero if no input is profitable.
+    let mut best = (low, U256::zero());
+    for amount_in in (0..=(high - low).as_u64()).map(|offset| low + offset) {
+        let profit = simulate_route(pool_map, route, amount_in).saturating_sub(amount_in);
+        if profit > best.1 {
+            best = (amount_in, profit);
+        }
+    }
+    match best.1.is_zero() {
+        true => U256::zero(),
+        false => best.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        best_amount_in, decode_swap, find_route_list, simulate_route, IUniswapV2SwapRouterCalls,
+        SwapExactETHForTokensCall,
+    };
+    use cfmms::pool::UniswapV2Pool;
+    use ethers::{abi::AbiEncode, prelude::*};
+    use std::collections::HashMap;
+
+    fn to_pool(
+        address: u64,
+        token_a: Address,
+        token_b: Address,
+        reserve: (u128, u128),
+    ) -> UniswapV2Pool {
+        UniswapV2Pool {
+            address: Address::from_low_u64_be(address),
+            token_a,
+            token_b,
+            reserve_0: reserve.0,
+            reserve_1: reserve.1,
+            fee: 300,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_pending_swap() {
+        let path = vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)];
+        let tx = Transaction {
+            to: Some(Address::from_low_u64_be(3)),
+            value: U256::exp10(18),
+            input: IUniswapV2SwapRouterCalls::SwapExactETHForTokens(SwapExactETHForTokensCall {
+                amount_out_min: U256::zero(),
+                path: path.clone(),
+                to: Address::zero(),
+                deadline: U256::MAX,
+            })
+            .encode()
+            .into(),
+            ..Default::default()
+        };
+
+        let swap = decode_swap(&tx).unwrap();
+        assert_eq!(swap.router, Address::from_low_u64_be(3));
+        assert_eq!(swap.path, path);
+        assert_eq!(swap.amount_in, U256::exp10(18));
+        assert_eq!(decode_swap(&Transaction::default()), None);
+    }
+
+    #[tokio::test]
+    async fn arbitrage_moved_pool() {
+        let wrapped_native = Address::from_low_u64_be(100);
+        let token = Address::from_low_u64_be(101);
+        let ether = 10_u128.pow(18);
+        let mut pool_map = HashMap::new();
+        // The victim buys the token on the first pool, the token is cheaper on the second pool.
+        let moved = to_pool(1, wrapped_native, token, (150 * ether, 66 * ether));
+        let other = to_pool(2, token, wrapped_native, (100 * ether, 100 * ether));
+        pool_map.insert(moved.address, moved);
+        pool_map.insert(other.address, other);
+
+        let route_list = find_route_list(&pool_map, wrapped_native, &[other.address]);
+        assert_eq!(route_list.len(), 2);
+        let route_list = find_route_list(&pool_map, wrapped_native, &[moved.address]);
+        let route = route_list
+            .iter()
+            .find(|route| route[0].pool == other.address)
+            .unwrap();
+        assert_eq!(route[0].token_out, token);
+
+        let amount_in = best_amount_in(&pool_map, route, U256::exp10(20));
+        let profit = simulate_route(&pool_map, route, amount_in) - amount_in;
+        assert!(!profit.is_zero());
+        for amount in [amount_in / 2, amount_in * 3 / 2] {
+            assert!(simulate_route(&pool_map, route, amount).saturating_sub(amount) <= profit);
+        }
+        // The reverse cycle loses.
+        let reverse = route_list
+            .iter()
+            .find(|route| route[0].pool == moved.address)
+            .unwrap();
+        assert!(best_amount_in(&pool_map, reverse, U256::exp10(20)).is_zero());
+    }
+}
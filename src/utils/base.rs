@@ -1,12 +1,29 @@
-use ethers::prelude::*;
+use crate::utils::ArbitrageError;
 use ethers::utils::format_units;
+use ethers::{abi::Detokenize, contract::builders::ContractCall, prelude::*};
 use std::env;
 use std::future::Future;
+use url::Url;
 
 pub fn get_env(name: &str) -> String {
     env::var(name).expect(&format!("Expect environment variable <{}>", name))
 }
 
+// Call through the client directly, the future of `ContractCall::call` isn't `Send` here.
+pub async fn call_contract<M: Middleware + 'static, S: Signer + 'static, C, D: Detokenize>(
+    client: &SignerMiddleware<M, S>,
+    call: ContractCall<C, D>,
+) -> Result<D, ArbitrageError> {
+    let ContractCall {
+        tx,
+        function,
+        block,
+        ..
+    } = call;
+    let output = client.call(&tx, block).await?;
+    Ok(decode_function_data(&function, output, false)?)
+}
+
 pub async fn log_profit<M: Middleware, S: Signer, Fut: Future<Output = ()>, F: FnOnce() -> Fut>(
     client: &SignerMiddleware<M, S>,
     address: Address,
@@ -32,3 +49,74 @@ pub async fn log_profit<M: Middleware, S: Signer, Fut: Future<Output = ()>, F: F
     println!("Balance diff:    {balance_diff:.6} eth");
     println!("----------------{:-^75}----------------\n", "");
 }
+
+// Wrapped native token of the chain, e.g. WETH on ethereum.
+pub fn wrapped_native(chain_id: u64) -> Option<Address> {
+    match chain_id {
+        1 => "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().ok(),
+        5 => "0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6".parse().ok(),
+        56 => "0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c".parse().ok(),
+        137 => "0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270".parse().ok(),
+        _ => None,
+    }
+}
+
+// Uniswap v2 / v3 style routers of the chain, used to swap the token profit to native token.
+// The v3 router is the one with the deadline in `ExactInputParams`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SwapRouter {
+    pub v2: Option<Address>,
+    pub v3: Option<Address>,
+}
+
+pub fn swap_router(chain_id: u64) -> SwapRouter {
+    let (v2, v3) = match chain_id {
+        1 | 5 => (
+            "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D",
+            "0xE592427A0AEce92De3Edee1F18E0157C05861564",
+        ),
+        // pancakeswap
+        56 => (
+            "0x10ED43C718714eb63d5aA57B78B54704E256024E",
+            "0x1b81D678ffb9C0263b24A97847620C99d213eB14",
+        ),
+        // quickswap and uniswap v3
+        137 => (
+            "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff",
+            "0xE592427A0AEce92De3Edee1F18E0157C05861564",
+        ),
+        _ => return SwapRouter::default(),
+    };
+    SwapRouter {
+        v2: v2.parse().ok(),
+        v3: v3.parse().ok(),
+    }
+}
+
+// ERC-3156 flash lenders of the chain without fee, e.g. MakerDAO `DssFlash` lends DAI.
+// Deploy `contract/BalancerFlashLender.sol` for the other tokens and set it by `flash_lenders` of the config.
+pub fn flash_lender_list(chain_id: u64) -> Vec<Address> {
+    match chain_id {
+        1 => vec!["0x60744434d6339a6B27d73d9Eda62b6F66a0a04FA"
+            .parse()
+            .unwrap()],
+        _ => Vec::new(),
+    }
+}
+
+// (chain id, relay) of the default bundle relays, the first relay of the chain also simulates the bundle.
+const RELAY_LIST: [(u64, &str); 4] = [
+    (1, "https://relay.flashbots.net"),
+    (1, "https://rpc.beaverbuild.org"),
+    (1, "https://rpc.titanbuilder.xyz"),
+    (5, "https://relay-goerli.flashbots.net"),
+];
+
+// Default bundle relays of the chain, see `relays` of the config to customize.
+pub fn relay_list(chain_id: u64) -> Vec<Url> {
+    RELAY_LIST
+        .iter()
+        .filter(|(id, _)| *id == chain_id)
+        .map(|(_, relay)| relay.parse().unwrap())
+        .collect()
+}
@@ -0,0 +1,357 @@
+use crate::utils::{ArbitrageError, BundleTx, FlashBotUtil};
+use ethers::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+// The number of observed bids kept by `Competitive`.
+const MAX_OBSERVED_BID: usize = 32;
+
+// Input of the bribe policy.
+#[derive(Debug, Clone, Default)]
+pub struct BribeContext {
+    // The profit in wei, e.g. the profit of `Simulate::run`.
+    pub profit: U256,
+    // The gas fee in wei, paid by the signer besides the bribe.
+    pub gas_cost: U256,
+}
+
+impl BribeContext {
+    pub fn new(profit: U256) -> Self {
+        Self {
+            profit,
+            gas_cost: U256::zero(),
+        }
+    }
+
+    // Estimate the gas cost of the bundle at the base fee of the next block, see `FlashBotUtil::estimate_gas`.
+    // The bundle is built without bribe, the coinbase transfer costs a little more gas.
+    pub async fn init<T: Into<BundleTx>>(
+        flashbot: &FlashBotUtil,
+        profit: U256,
+        bundle: Vec<T>,
+    ) -> Result<Self, ArbitrageError> {
+        let gas_used = flashbot.estimate_gas(bundle).await?;
+        Self::init_with_gas_used(flashbot, profit, gas_used).await
+    }
+
+    // The gas can't be estimated alone if the tx depends on the others in the bundle, e.g. the backrun tx.
+    pub async fn init_with_gas_used<M: Middleware + 'static, S: Signer + 'static>(
+        client: &SignerMiddleware<M, S>,
+        profit: U256,
+        gas_used: U256,
+    ) -> Result<Self, ArbitrageError> {
+        let block = client
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| ArbitrageError::rpc("Get latest block error"))?;
+        let base_fee = block.next_block_base_fee().unwrap_or_default();
+
+        Ok(Self {
+            profit,
+            gas_cost: gas_used * base_fee,
+        })
+    }
+
+    // The profit after the gas fee, none if the gas fee eats all the profit.
+    pub fn net_profit(&self) -> Option<U256> {
+        self.profit
+            .checked_sub(self.gas_cost)
+            .filter(|profit| !profit.is_zero())
+    }
+}
+
+// Decide the fee sent to `block.coinbase`, see `ArbitrageUtil::to_tx`.
+pub trait BribePolicy: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // @return The fee sent to coinbase, none to give up the bundle
+    fn bribe(&self, context: &BribeContext) -> Option<U256>;
+
+    // Feed the coinbase payment of a competing bundle, e.g. the winner of the last block.
+    fn observe(&self, _bid: U256) {}
+}
+
+// No bribe, the bundle only competes with the gas price.
+#[derive(Debug, Clone, Default)]
+pub struct NoBribe;
+
+impl BribePolicy for NoBribe {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn bribe(&self, context: &BribeContext) -> Option<U256> {
+        context.net_profit().map(|_| U256::zero())
+    }
+}
+
+// Pay a fixed fee, give up if it isn't covered by the profit.
+#[derive(Debug, Clone)]
+pub struct FixedBribe(pub U256);
+
+impl BribePolicy for FixedBribe {
+    fn name(&self) -> &str {
+        "fixed"
+    }
+
+    fn bribe(&self, context: &BribeContext) -> Option<U256> {
+        context
+            .net_profit()
+            .filter(|profit| *profit >= self.0)
+            .map(|_| self.0)
+    }
+}
+
+// Pay a share of the profit after gas, capped in wei.
+#[derive(Debug, Clone)]
+pub struct ProfitShare {
+    percent: u64,
+    cap: Option<U256>,
+}
+
+impl ProfitShare {
+    pub fn new(percent: u64) -> Self {
+        Self {
+            percent: percent.min(100),
+            cap: None,
+        }
+    }
+
+    pub fn with_cap(mut self, cap: U256) -> Self {
+        self.cap = Some(cap);
+        self
+    }
+}
+
+impl BribePolicy for ProfitShare {
+    fn name(&self) -> &str {
+        "share"
+    }
+
+    fn bribe(&self, context: &BribeContext) -> Option<U256> {
+        let bribe = context.net_profit()? * self.percent / 100;
+        Some(self.cap.map_or(bribe, |cap| bribe.min(cap)))
+    }
+}
+
+// Pay all the profit but keep a minimum margin after gas.
+#[derive(Debug, Clone)]
+pub struct MinMargin(pub U256);
+
+impl BribePolicy for MinMargin {
+    fn name(&self) -> &str {
+        "margin"
+    }
+
+    fn bribe(&self, context: &BribeContext) -> Option<U256> {
+        context.net_profit()?.checked_sub(self.0)
+    }
+}
+
+// Outbid the highest observed bid by a step in percent, the fallback policy is used before any bid is observed.
+// Give up if the bid exceeds the profit after gas.
+#[derive(Debug)]
+pub struct Competitive {
+    step_percent: u64,
+    fallback: Box<dyn BribePolicy>,
+    bid_list: Mutex<VecDeque<U256>>,
+}
+
+impl Competitive {
+    pub fn new(step_percent: u64, fallback: Box<dyn BribePolicy>) -> Self {
+        Self {
+            step_percent,
+            fallback,
+            bid_list: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl BribePolicy for Competitive {
+    fn name(&self) -> &str {
+        "competitive"
+    }
+
+    fn bribe(&self, context: &BribeContext) -> Option<U256> {
+        let highest = self.bid_list.lock().unwrap().iter().max().copied();
+        match highest {
+            Some(highest) => {
+                let bribe = highest * (100 + self.step_percent) / 100;
+                context.net_profit().filter(|profit| *profit > bribe)?;
+                Some(bribe)
+            }
+            None => self.fallback.bribe(context),
+        }
+    }
+
+    fn observe(&self, bid: U256) {
+        let mut bid_list = self.bid_list.lock().unwrap();
+        if bid_list.len() == MAX_OBSERVED_BID {
+            bid_list.pop_front();
+        }
+        bid_list.push_back(bid);
+    }
+}
+
+// The coinbase payments of the block, the highest one is the bid to beat, see `BribePolicy::observe`.
+// The transfers from the contracts, e.g. the bribes of the bundles, are found only if `trace` is on,
+// `trace_block` is a parity method, only the direct transfers are found otherwise.
+// @param exclude The sender whose payments aren't bids to beat, e.g. our signer
+pub async fn coinbase_payment_list<M: Middleware + 'static, S: Signer + 'static>(
+    client: &SignerMiddleware<M, S>,
+    block_number: U64,
+    exclude: Address,
+    trace: bool,
+) -> Result<Vec<U256>, ArbitrageError> {
+    let block = client
+        .get_block_with_txs(block_number)
+        .await?
+        .ok_or_else(|| ArbitrageError::rpc("Get block error"))?;
+    let trace_list = match trace {
+        true => client.trace_block(block_number.into()).await?,
+        false => Vec::new(),
+    };
+    Ok(to_coinbase_payment_list(&block, &trace_list, exclude))
+}
+
+fn to_coinbase_payment_list(
+    block: &Block<Transaction>,
+    trace_list: &[Trace],
+    exclude: Address,
+) -> Vec<U256> {
+    let coinbase = match block.author {
+        Some(coinbase) => coinbase,
+        None => return Vec::new(),
+    };
+    let mut payment_map = HashMap::<TxHash, U256>::new();
+    for trace in trace_list {
+        if let (Action::Call(call), Some(tx_hash)) = (&trace.action, trace.transaction_hash) {
+            if call.to == coinbase && !call.value.is_zero() {
+                *payment_map.entry(tx_hash).or_default() += call.value;
+            }
+        }
+    }
+    // The direct transfers are in the traces as well.
+    if trace_list.is_empty() {
+        for tx in &block.transactions {
+            if tx.to == Some(coinbase) && !tx.value.is_zero() {
+                payment_map.insert(tx.hash, tx.value);
+            }
+        }
+    }
+
+    block
+        .transactions
+        .iter()
+        .filter(|tx| tx.from != exclude)
+        .filter_map(|tx| payment_map.get(&tx.hash).copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        to_coinbase_payment_list, BribeContext, BribePolicy, Competitive, FixedBribe, MinMargin,
+        ProfitShare,
+    };
+    use ethers::prelude::*;
+
+    #[tokio::test]
+    async fn bribe_after_gas_cost() {
+        let context = BribeContext {
+            profit: U256::from(1000),
+            gas_cost: U256::from(200),
+        };
+
+        assert_eq!(ProfitShare::new(70).bribe(&context), Some(U256::from(560)));
+        assert_eq!(
+            ProfitShare::new(70)
+                .with_cap(U256::from(100))
+                .bribe(&context),
+            Some(U256::from(100))
+        );
+        assert_eq!(
+            MinMargin(U256::from(300)).bribe(&context),
+            Some(U256::from(500))
+        );
+        assert_eq!(MinMargin(U256::from(900)).bribe(&context), None);
+        assert_eq!(FixedBribe(U256::from(900)).bribe(&context), None);
+
+        let context = BribeContext {
+            profit: U256::from(100),
+            gas_cost: U256::from(200),
+        };
+        assert_eq!(ProfitShare::new(70).bribe(&context), None);
+    }
+
+    #[tokio::test]
+    async fn outbid_observed_bid() {
+        let policy = Competitive::new(10, Box::new(ProfitShare::new(50)));
+        let context = BribeContext::new(U256::from(1000));
+        assert_eq!(policy.bribe(&context), Some(U256::from(500)));
+
+        policy.observe(U256::from(600));
+        policy.observe(U256::from(300));
+        assert_eq!(policy.bribe(&context), Some(U256::from(660)));
+
+        policy.observe(U256::from(1000));
+        assert_eq!(policy.bribe(&context), None);
+    }
+
+    #[tokio::test]
+    async fn observe_coinbase_payment() {
+        let coinbase = Address::random();
+        let exclude = Address::random();
+        let to_tx = |from: Address, to: Address, value: u64| Transaction {
+            hash: TxHash::random(),
+            from,
+            to: Some(to),
+            value: U256::from(value),
+            ..Default::default()
+        };
+        // A direct transfer, a bundle paying from its contract and our own bribe.
+        let tx_list = vec![
+            to_tx(Address::random(), coinbase, 100),
+            to_tx(Address::random(), Address::random(), 0),
+            to_tx(exclude, Address::random(), 0),
+        ];
+        let to_trace = |tx: &Transaction, from: Address, value: u64| Trace {
+            action: Action::Call(Call {
+                from,
+                to: coinbase,
+                value: U256::from(value),
+                ..Default::default()
+            }),
+            result: None,
+            trace_address: vec![0],
+            subtraces: 0,
+            transaction_position: None,
+            transaction_hash: Some(tx.hash),
+            block_number: 1,
+            block_hash: H256::zero(),
+            action_type: ActionType::Call,
+            error: None,
+        };
+        let trace_list = vec![
+            to_trace(&tx_list[0], tx_list[0].from, 100),
+            to_trace(&tx_list[1], Address::random(), 300),
+            to_trace(&tx_list[2], Address::random(), 900),
+        ];
+        let block = Block {
+            author: Some(coinbase),
+            transactions: tx_list,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_coinbase_payment_list(&block, &trace_list, exclude),
+            vec![U256::from(100), U256::from(300)]
+        );
+        // Only the direct transfer without the traces.
+        assert_eq!(
+            to_coinbase_payment_list(&block, &[], exclude),
+            vec![U256::from(100)]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::utils::{ArbitrageRevert, ErrorSource};
+use ethers::utils::{format_units, hex};
+use ethers::{abi::AbiDecode, prelude::*};
+use ethers_flashbots::{SimulatedBundle, SimulatedTransaction};
+use std::error::Error;
+use std::fmt;
+
+// The selector of `Error(string)`, e.g. `require` with a message.
+const REVERT_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+// The `require` messages of the pools and routers once the price moves or the deadline passes.
+const SLIPPAGE_MESSAGE_LIST: [&str; 9] = [
+    "INSUFFICIENT_OUTPUT_AMOUNT",
+    "INSUFFICIENT_INPUT_AMOUNT",
+    "EXCESSIVE_INPUT_AMOUNT",
+    "INSUFFICIENT_LIQUIDITY",
+    "UniswapV2: K",
+    "Too little received",
+    "Too much requested",
+    "Price slippage check",
+    "Transaction too old",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    // The custom error of the arbitrage contract, e.g. `SufficientIncome`.
+    Contract(ArbitrageRevert),
+    Message(String),
+    // The undecoded revert data or the node error, e.g. out of gas.
+    Other(String),
+}
+
+impl RevertReason {
+    // The relay returns the revert data in hex, or the raw bytes as a string.
+    pub fn decode(revert: &str) -> Self {
+        let data = revert
+            .strip_prefix("0x")
+            .and_then(|data| hex::decode(data).ok())
+            .unwrap_or_else(|| revert.as_bytes().to_vec());
+        Self::from_data(&data).unwrap_or_else(|| Self::Other(revert.into()))
+    }
+
+    // @return None if the data is neither a custom error of the contract nor `Error(string)`
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if let Some(revert) = ArbitrageRevert::decode(data) {
+            return Some(Self::Contract(revert));
+        }
+        if data.starts_with(&REVERT_STRING_SELECTOR) {
+            if let Ok(message) = String::decode(&data[4..]) {
+                return Some(Self::Message(message));
+            }
+        }
+        None
+    }
+
+    // Only the known reverts of the opportunity being gone, e.g. the slippage after the state changes.
+    // The others may be the infrastructure, e.g. the nonce is too low or the bundle is malformed.
+    pub fn is_unprofitable(&self) -> bool {
+        match self {
+            Self::Contract(revert) => revert.is_unprofitable(),
+            Self::Message(message) => SLIPPAGE_MESSAGE_LIST
+                .iter()
+                .any(|slippage| message.contains(slippage)),
+            Self::Other(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Contract(revert) => write!(f, "{revert}"),
+            Self::Message(message) => write!(f, "{message:?}"),
+            Self::Other(revert) => write!(f, "{revert}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulatedTx {
+    pub hash: TxHash,
+    pub gas_used: U256,
+    pub coinbase_diff: U256,
+    pub effective_gas_price: U256,
+    pub revert: Option<RevertReason>,
+}
+
+impl From<&SimulatedTransaction> for SimulatedTx {
+    fn from(tx: &SimulatedTransaction) -> Self {
+        Self {
+            hash: tx.hash,
+            gas_used: tx.gas_used,
+            coinbase_diff: tx.coinbase_diff,
+            effective_gas_price: tx.effective_gas_price(),
+            revert: match (&tx.revert, &tx.error) {
+                (Some(revert), _) => Some(RevertReason::decode(revert)),
+                (None, Some(error)) => Some(RevertReason::Other(error.clone())),
+                (None, None) => None,
+            },
+        }
+    }
+}
+
+// The result of `eth_callBundle` on top of the simulation block.
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub simulation_block: U64,
+    pub gas_used: U256,
+    pub coinbase_diff: U256,
+    pub effective_gas_price: U256,
+    pub tx_list: Vec<SimulatedTx>,
+}
+
+impl SimulationReport {
+    // The first reverted tx, the whole bundle fails with it.
+    pub fn revert(&self) -> Option<&SimulatedTx> {
+        self.tx_list.iter().find(|tx| tx.revert.is_some())
+    }
+
+    // The gas used without the txs of others, e.g. the victim tx of the sandwich bundle.
+    pub fn gas_used_without(&self, other_list: &[TxHash]) -> U256 {
+        self.tx_list
+            .iter()
+            .filter(|tx| !other_list.contains(&tx.hash))
+            .fold(U256::zero(), |gas_used, tx| gas_used + tx.gas_used)
+    }
+}
+
+impl From<SimulatedBundle> for SimulationReport {
+    fn from(bundle: SimulatedBundle) -> Self {
+        Self {
+            simulation_block: bundle.simulation_block,
+            gas_used: bundle.gas_used,
+            coinbase_diff: bundle.coinbase_diff,
+            effective_gas_price: bundle.effective_gas_price(),
+            tx_list: bundle.transactions.iter().map(SimulatedTx::from).collect(),
+        }
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gas used {}, coinbase diff {} ETH, effective gas price {} gwei",
+            self.gas_used,
+            format_units(self.coinbase_diff, "ether").unwrap_or_default(),
+            format_units(self.effective_gas_price, "gwei").unwrap_or_default()
+        )?;
+        for tx in &self.tx_list {
+            write!(
+                f,
+                "\n    {:?}: gas used {}, coinbase diff {}",
+                tx.hash, tx.gas_used, tx.coinbase_diff
+            )?;
+            if let Some(revert) = &tx.revert {
+                write!(f, ", reverted: {revert}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SimulationError {
+    // The relay can't simulate the bundle, e.g. the nonce is too low.
+    Relay(ErrorSource),
+    Reverted {
+        tx_hash: TxHash,
+        reason: RevertReason,
+    },
+    CoinbaseDiff {
+        coinbase_diff: U256,
+        min: U256,
+    },
+    EffectiveGasPrice {
+        effective_gas_price: U256,
+        min: U256,
+    },
+    GasUsed {
+        gas_used: U256,
+        max: U256,
+    },
+}
+
+impl SimulationError {
+    // Our contract reverts the same on the next block, e.g. `SufficientIncome` if the opportunity is gone.
+    // The relay error and the other checks may pass on the next block, e.g. the bribe is rebuilt.
+    pub fn is_deterministic(&self) -> bool {
+        matches!(
+            self,
+            Self::Reverted {
+                reason: RevertReason::Contract(_),
+                ..
+            }
+        )
+    }
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Relay(error) => write!(f, "Bundle simulation error: {error}"),
+            Self::Reverted { tx_hash, reason } => {
+                write!(f, "Bundle tx {tx_hash:?} reverted: {reason}")
+            }
+            Self::CoinbaseDiff { coinbase_diff, min } => {
+                write!(f, "Bundle coinbase diff {coinbase_diff} is less than {min}")
+            }
+            Self::EffectiveGasPrice {
+                effective_gas_price,
+                min,
+            } => write!(
+                f,
+                "Bundle effective gas price {effective_gas_price} is less than {min}"
+            ),
+            Self::GasUsed { gas_used, max } => {
+                write!(f, "Bundle gas used {gas_used} is more than {max}")
+            }
+        }
+    }
+}
+
+impl Error for SimulationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Relay(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// The bundle is sent only if the simulation passes all checks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulationCheck {
+    // The payment to the builder, too low to be included.
+    pub min_coinbase_diff: U256,
+    pub min_effective_gas_price: U256,
+    // None for unlimited
+    pub max_gas_used: Option<U256>,
+}
+
+impl SimulationCheck {
+    // The whole sequence is checked, e.g. the victim tx reverts or the sandwich loses.
+    pub fn check(&self, report: &SimulationReport) -> Result<(), SimulationError> {
+        if let Some(tx) = report.revert() {
+            return Err(SimulationError::Reverted {
+                tx_hash: tx.hash,
+                reason: tx.revert.clone().unwrap(),
+            });
+        }
+        if report.coinbase_diff < self.min_coinbase_diff {
+            return Err(SimulationError::CoinbaseDiff {
+                coinbase_diff: report.coinbase_diff,
+                min: self.min_coinbase_diff,
+            });
+        }
+        if report.effective_gas_price < self.min_effective_gas_price {
+            return Err(SimulationError::EffectiveGasPrice {
+                effective_gas_price: report.effective_gas_price,
+                min: self.min_effective_gas_price,
+            });
+        }
+        match self.max_gas_used {
+            Some(max) if report.gas_used > max => Err(SimulationError::GasUsed {
+                gas_used: report.gas_used,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RevertReason, SimulatedTx, SimulationCheck, SimulationError, SimulationReport};
+    use crate::utils::{ArbitrageContractErrors, ArbitrageRevert, SufficientIncome};
+    use ethers::utils::hex;
+    use ethers::{abi::AbiEncode, prelude::*};
+
+    fn to_report(coinbase_diff: u64, revert: Option<RevertReason>) -> SimulationReport {
+        SimulationReport {
+            simulation_block: U64::from(1),
+            gas_used: U256::from(100_000),
+            coinbase_diff: U256::from(coinbase_diff),
+            effective_gas_price: U256::from(coinbase_diff / 100_000),
+            tx_list: vec![SimulatedTx {
+                hash: TxHash::zero(),
+                gas_used: U256::from(100_000),
+                coinbase_diff: U256::from(coinbase_diff),
+                effective_gas_price: U256::from(coinbase_diff / 100_000),
+                revert,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_revert_reason() {
+        let sufficient_income = ArbitrageContractErrors::SufficientIncome(SufficientIncome);
+        let revert = format!("0x{}", hex::encode(sufficient_income.encode()));
+        assert_eq!(
+            RevertReason::decode(&revert),
+            RevertReason::Contract(ArbitrageRevert::SufficientIncome)
+        );
+        assert_eq!(
+            RevertReason::decode(&revert).to_string(),
+            "SufficientIncome"
+        );
+
+        // Error(string) of `require(false, "STF")`
+        let revert = format!("0x08c379a0{}", hex::encode("STF".to_string().encode()));
+        assert_eq!(
+            RevertReason::decode(&revert),
+            RevertReason::Message("STF".into())
+        );
+        assert_eq!(
+            RevertReason::decode("out of gas"),
+            RevertReason::Other("out of gas".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn classify_revert_reason() {
+        assert!(RevertReason::Contract(ArbitrageRevert::SufficientIncome).is_unprofitable());
+        assert!(
+            RevertReason::Message("UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT".into())
+                .is_unprofitable()
+        );
+        assert!(
+            !RevertReason::Message("Ownable: caller is not the owner".into()).is_unprofitable()
+        );
+        // The node errors are the infrastructure, not the opportunity.
+        assert!(!RevertReason::Other("nonce too low".into()).is_unprofitable());
+        assert!(
+            !RevertReason::Other("insufficient funds for gas * price + value".into())
+                .is_unprofitable()
+        );
+    }
+
+    #[tokio::test]
+    async fn check_simulation_report() {
+        let check = SimulationCheck {
+            min_coinbase_diff: U256::from(1_000_000_000_000_u64),
+            max_gas_used: Some(U256::from(200_000)),
+            ..Default::default()
+        };
+        assert!(check.check(&to_report(2_000_000_000_000, None)).is_ok());
+        assert_eq!(
+            to_report(1, None).gas_used_without(&[]),
+            U256::from(100_000)
+        );
+        assert_eq!(
+            to_report(1, None).gas_used_without(&[TxHash::zero()]),
+            U256::zero()
+        );
+        assert!(matches!(
+            check.check(&to_report(1, None)),
+            Err(SimulationError::CoinbaseDiff { .. })
+        ));
+
+        let report = to_report(
+            2_000_000_000_000,
+            Some(RevertReason::Other("out of gas".into())),
+        );
+        assert_eq!(
+            check.check(&report).unwrap_err().to_string(),
+            format!("Bundle tx {:?} reverted: out of gas", TxHash::zero())
+        );
+        assert!(!check.check(&report).unwrap_err().is_deterministic());
+        assert!(!check
+            .check(&to_report(1, None))
+            .unwrap_err()
+            .is_deterministic());
+
+        let report = to_report(
+            2_000_000_000_000,
+            Some(RevertReason::Contract(ArbitrageRevert::SufficientIncome)),
+        );
+        assert!(check.check(&report).unwrap_err().is_deterministic());
+    }
+}
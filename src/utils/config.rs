@@ -0,0 +1,704 @@
+use crate::utils::{
+    flash_lender_list, relay_list, BribePolicy, Competitive, FixedBribe, MinMargin, NoBribe,
+    ProfitShare, RelaySignerSource, SimulationCheck, DEFAULT_QUEUE_CAPACITY,
+};
+use ethers::prelude::*;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use url::Url;
+
+const DEFAULT_CONFIG_PATH: &str = "frontrun.toml";
+const PRIORITY_SPEC: &str = "Expect `none`, `fixed:<WEI>`, `share:<PERCENT>[:<CAP_WEI>]`, `margin:<WEI>` or `competitive:<STEP_PERCENT>:<SHARE_PERCENT>`";
+
+pub const USAGE: &str = "Usage: frontrun [OPTIONS]
+
+The options override the config file, which overrides the environment variables.
+
+Options:
+    --config <PATH>             Config file, default `frontrun.toml` if exists
+    --http-rpc-url <URL>        HTTP rpc endpoint [env: HTTP_RPC_URL]
+    --wss-rpc-url <URL>         Websocket rpc endpoint [env: WSS_RPC_URL]
+    --mempool-source <URL>      Another websocket node merged into the pending txs, can be repeated
+    --chain-id <ID>             Chain id [env: CHAIN_ID]
+    --contract <ADDRESS>        Arbitrage contract [env: CONTRACT]
+    --private-key <KEY>         Signer private key [env: PRIVATE_KEY]
+    --relay-private-key <KEY>   Relay reputation signer private key [env: RELAY_PRIVATE_KEY]
+    --relay-keystore <PATH>     Relay reputation signer keystore, reloaded once modified
+    --relay-keystore-password <PASSWORD>
+                                Password of the relay keystore [env: RELAY_KEYSTORE_PASSWORD]
+    --max-concurrent <N>        Max concurrent simulations, 0 for unlimited, default 1
+    --queue-capacity <N>        Max pending txs waiting for the simulations, the lowest gas price
+                                is dropped once full, default 1024
+    --uncle-protect <BOOL>      Revert in the uncle block, default true
+    --max-block <N>             Resubmit the bundle for at most N blocks, default 3
+    --priority <POLICY>         Bribe policy, default `none`, one of:
+                                `none`, `fixed:<WEI>`, `share:<PERCENT>[:<CAP_WEI>]`, `margin:<WEI>`
+                                or `competitive:<STEP_PERCENT>:<SHARE_PERCENT>` outbidding the coinbase
+                                payments of the last blocks, traced only with the `parity` backend
+    --mode <MODE>               `frontrun` the pending tx, `backrun` the pending swap or `sandwich` it with both,
+                                default `frontrun`
+    --backend <BACKEND>         Simulate backend: `parity`, `geth` or `evm`, default `parity`
+    --relay <URL>               Bundle relay, can be repeated, default by chain
+    --min-coinbase-diff <WEI>   Send the bundle only if the simulated coinbase payment reaches it, default 0
+    --min-gas-price <WEI>       Send the bundle only if the simulated effective gas price reaches it, default 0
+    --max-gas-used <GAS>        Send the bundle only if the simulated gas used is within it, default unlimited
+    --bloxroute-auth <AUTH>     Also send the bundle to bloXroute with the auth header [env: BLOXROUTE_AUTH]
+    --flash-lender <ADDRESS>    ERC-3156 lender of the required capital without fee, tried in order,
+                                can be repeated, default by chain, e.g. a deployed `BalancerFlashLender`
+    --analysis <NAME,...>       Enabled analyzers, default all
+    --replay-depth <DEPTH,...>  Call depths of the pending tx to replay, 0 for the origin call,
+                                1 for its subcalls and so on, default `0,1`
+    -h, --help                  Print help";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Help,
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownFlag(String),
+    MissingFlagValue(String),
+    Missing(&'static str),
+    Invalid {
+        key: &'static str,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Help => write!(f, "{USAGE}"),
+            Self::Read(path, error) => {
+                write!(f, "Failed to read config file {}: {error}", path.display())
+            }
+            Self::Parse(path, error) => {
+                write!(f, "Invalid config file {}: {error}", path.display())
+            }
+            Self::UnknownFlag(flag) => write!(f, "Unknown flag <{flag}>, see --help"),
+            Self::MissingFlagValue(flag) => write!(f, "Missing value of flag <{flag}>"),
+            Self::Missing(key) => write!(
+                f,
+                "Missing config <{key}>, set `{key}` in the config file, flag --{} or environment variable {}",
+                key.replace('_', "-"),
+                key.to_uppercase()
+            ),
+            Self::Invalid { key, value, reason } => {
+                write!(f, "Invalid config <{key}> = {value:?}: {reason}")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// The spec of the bribe policy, see `BribePolicy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Priority {
+    None,
+    // Fixed priority fee in wei.
+    Fixed(U256),
+    // Share of the profit in percent, capped in wei.
+    Share { percent: u64, cap: Option<U256> },
+    // Minimum margin kept in wei.
+    Margin(U256),
+    // Outbid the observed bids by the step in percent, share the profit before any bid.
+    Competitive { step_percent: u64, percent: u64 },
+}
+
+impl Priority {
+    pub fn to_policy(&self) -> Box<dyn BribePolicy> {
+        match self {
+            Self::None => Box::new(NoBribe),
+            Self::Fixed(fee) => Box::new(FixedBribe(*fee)),
+            Self::Share { percent, cap } => {
+                let policy = ProfitShare::new(*percent);
+                Box::new(match cap {
+                    Some(cap) => policy.with_cap(*cap),
+                    None => policy,
+                })
+            }
+            Self::Margin(margin) => Box::new(MinMargin(*margin)),
+            Self::Competitive {
+                step_percent,
+                percent,
+            } => Box::new(Competitive::new(
+                *step_percent,
+                Box::new(ProfitShare::new(*percent)),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // Copy the profitable calls of the pending tx, see `Simulate::run`.
+    Frontrun,
+    // Arbitrage the pools moved by the pending swap, see `Backrun::run`.
+    Backrun,
+    // [frontrun tx, pending swap, backrun tx], see `ArbitrageUtil::to_sandwich_bundle`.
+    Sandwich,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Parity,
+    Geth,
+    Evm,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub http_rpc_url: Url,
+    pub wss_rpc_url: Url,
+    // The other nodes besides `wss_rpc_url`
+    pub mempool_sources: Vec<Url>,
+    pub chain_id: u64,
+    pub contract: Address,
+    pub wallet: LocalWallet,
+    pub relay_signer: RelaySignerSource,
+    // None for unlimited
+    pub max_concurrent: Option<usize>,
+    pub queue_capacity: usize,
+    pub uncle_protect: bool,
+    pub max_block: u64,
+    pub priority: Priority,
+    pub mode: Mode,
+    pub backend: Backend,
+    pub relays: Vec<Url>,
+    pub simulation_check: SimulationCheck,
+    pub bloxroute_auth: Option<String>,
+    pub flash_lenders: Vec<Address>,
+    // None for all analyzers
+    pub analysis: Option<Vec<String>>,
+    pub replay_depth: Vec<usize>,
+}
+
+// Every layer is optional, the later layer overrides the former.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    http_rpc_url: Option<String>,
+    wss_rpc_url: Option<String>,
+    mempool_sources: Option<Vec<String>>,
+    chain_id: Option<u64>,
+    contract: Option<String>,
+    private_key: Option<String>,
+    relay_private_key: Option<String>,
+    relay_keystore: Option<PathBuf>,
+    relay_keystore_password: Option<String>,
+    max_concurrent: Option<usize>,
+    queue_capacity: Option<usize>,
+    uncle_protect: Option<bool>,
+    max_block: Option<u64>,
+    priority: Option<String>,
+    mode: Option<String>,
+    backend: Option<String>,
+    relays: Option<Vec<String>>,
+    min_coinbase_diff: Option<String>,
+    min_gas_price: Option<String>,
+    max_gas_used: Option<u64>,
+    bloxroute_auth: Option<String>,
+    flash_lenders: Option<Vec<String>>,
+    analysis: Option<Vec<String>>,
+    replay_depth: Option<Vec<usize>>,
+}
+
+impl RawConfig {
+    fn merge(self, other: RawConfig) -> RawConfig {
+        RawConfig {
+            http_rpc_url: other.http_rpc_url.or(self.http_rpc_url),
+            wss_rpc_url: other.wss_rpc_url.or(self.wss_rpc_url),
+            mempool_sources: other.mempool_sources.or(self.mempool_sources),
+            chain_id: other.chain_id.or(self.chain_id),
+            contract: other.contract.or(self.contract),
+            private_key: other.private_key.or(self.private_key),
+            relay_private_key: other.relay_private_key.or(self.relay_private_key),
+            relay_keystore: other.relay_keystore.or(self.relay_keystore),
+            relay_keystore_password: other
+                .relay_keystore_password
+                .or(self.relay_keystore_password),
+            max_concurrent: other.max_concurrent.or(self.max_concurrent),
+            queue_capacity: other.queue_capacity.or(self.queue_capacity),
+            uncle_protect: other.uncle_protect.or(self.uncle_protect),
+            max_block: other.max_block.or(self.max_block),
+            priority: other.priority.or(self.priority),
+            mode: other.mode.or(self.mode),
+            backend: other.backend.or(self.backend),
+            relays: other.relays.or(self.relays),
+            min_coinbase_diff: other.min_coinbase_diff.or(self.min_coinbase_diff),
+            min_gas_price: other.min_gas_price.or(self.min_gas_price),
+            max_gas_used: other.max_gas_used.or(self.max_gas_used),
+            bloxroute_auth: other.bloxroute_auth.or(self.bloxroute_auth),
+            flash_lenders: other.flash_lenders.or(self.flash_lenders),
+            analysis: other.analysis.or(self.analysis),
+            replay_depth: other.replay_depth.or(self.replay_depth),
+        }
+    }
+
+    fn from_env() -> Result<RawConfig, ConfigError> {
+        Ok(RawConfig {
+            http_rpc_url: env::var("HTTP_RPC_URL").ok(),
+            wss_rpc_url: env::var("WSS_RPC_URL").ok(),
+            chain_id: env::var("CHAIN_ID")
+                .ok()
+                .map(|value| parse("chain_id", &value))
+                .transpose()?,
+            contract: env::var("CONTRACT").ok(),
+            private_key: env::var("PRIVATE_KEY").ok(),
+            relay_private_key: env::var("RELAY_PRIVATE_KEY").ok(),
+            relay_keystore_password: env::var("RELAY_KEYSTORE_PASSWORD").ok(),
+            bloxroute_auth: env::var("BLOXROUTE_AUTH").ok(),
+            ..Default::default()
+        })
+    }
+
+    fn from_file(path: &Path) -> Result<RawConfig, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|error| ConfigError::Read(path.into(), error))?;
+        toml::from_str(&content).map_err(|error| ConfigError::Parse(path.into(), error))
+    }
+
+    // @return (config file path, raw config)
+    fn from_args<I: IntoIterator<Item = String>>(
+        args: I,
+    ) -> Result<(Option<PathBuf>, RawConfig), ConfigError> {
+        let mut path = None;
+        let mut raw = RawConfig::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            if flag == "-h" || flag == "--help" {
+                return Err(ConfigError::Help);
+            }
+            if !flag.starts_with("--") {
+                return Err(ConfigError::UnknownFlag(flag));
+            }
+            // Both `--flag value` and `--flag=value` are supported.
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingFlagValue(flag.clone()))?;
+                    (flag, value)
+                }
+            };
+
+            match flag.as_str() {
+                "--config" => path = Some(PathBuf::from(value)),
+                "--http-rpc-url" => raw.http_rpc_url = Some(value),
+                "--wss-rpc-url" => raw.wss_rpc_url = Some(value),
+                "--mempool-source" => raw.mempool_sources.get_or_insert_with(Vec::new).push(value),
+                "--chain-id" => raw.chain_id = Some(parse("chain_id", &value)?),
+                "--contract" => raw.contract = Some(value),
+                "--private-key" => raw.private_key = Some(value),
+                "--relay-private-key" => raw.relay_private_key = Some(value),
+                "--relay-keystore" => raw.relay_keystore = Some(PathBuf::from(value)),
+                "--relay-keystore-password" => raw.relay_keystore_password = Some(value),
+                "--max-concurrent" => raw.max_concurrent = Some(parse("max_concurrent", &value)?),
+                "--queue-capacity" => raw.queue_capacity = Some(parse("queue_capacity", &value)?),
+                "--uncle-protect" => raw.uncle_protect = Some(parse("uncle_protect", &value)?),
+                "--max-block" => raw.max_block = Some(parse("max_block", &value)?),
+                "--priority" => raw.priority = Some(value),
+                "--mode" => raw.mode = Some(value),
+                "--backend" => raw.backend = Some(value),
+                "--relay" => raw.relays.get_or_insert_with(Vec::new).push(value),
+                "--min-coinbase-diff" => raw.min_coinbase_diff = Some(value),
+                "--min-gas-price" => raw.min_gas_price = Some(value),
+                "--max-gas-used" => raw.max_gas_used = Some(parse("max_gas_used", &value)?),
+                "--bloxroute-auth" => raw.bloxroute_auth = Some(value),
+                "--flash-lender" => raw.flash_lenders.get_or_insert_with(Vec::new).push(value),
+                "--analysis" => {
+                    raw.analysis = Some(value.split(',').map(|name| name.trim().into()).collect())
+                }
+                "--replay-depth" => {
+                    raw.replay_depth = Some(
+                        value
+                            .split(',')
+                            .map(|depth| parse("replay_depth", depth))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+
+        Ok((path, raw))
+    }
+
+    fn build(self) -> Result<Config, ConfigError> {
+        let chain_id = self.chain_id.unwrap_or(1);
+        let private_key = self
+            .private_key
+            .ok_or(ConfigError::Missing("private_key"))?;
+        let wallet = parse_private_key("private_key", &private_key)?.with_chain_id(chain_id);
+
+        let relay_signer = match (
+            self.relay_private_key,
+            self.relay_keystore,
+            self.relay_keystore_password,
+        ) {
+            (Some(_), Some(_), _) => {
+                return Err(ConfigError::Invalid {
+                    key: "relay_keystore",
+                    value: String::new(),
+                    reason: "Set either `relay_private_key` or `relay_keystore`".into(),
+                })
+            }
+            (Some(relay_private_key), None, _) => {
+                RelaySignerSource::Key(parse_private_key("relay_private_key", &relay_private_key)?)
+            }
+            (None, Some(path), Some(password)) => RelaySignerSource::Keystore { path, password },
+            (None, Some(_), None) => return Err(ConfigError::Missing("relay_keystore_password")),
+            (None, None, _) => RelaySignerSource::Random,
+        };
+        // The relay signer is public to the relay, it mustn't hold the funds.
+        if matches!(&relay_signer, RelaySignerSource::Key(relay_wallet) if relay_wallet.address() == wallet.address())
+        {
+            return Err(ConfigError::Invalid {
+                key: "relay_private_key",
+                value: "<hidden>".into(),
+                reason: "Expect a different key from `private_key`".into(),
+            });
+        }
+
+        let relays = match self.relays {
+            Some(relays) => relays,
+            None => relay_list(chain_id)
+                .iter()
+                .map(|relay| relay.to_string())
+                .collect(),
+        };
+        if relays.is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "relays",
+                value: String::new(),
+                reason: format!("No default relay for chain {chain_id}, set at least one"),
+            });
+        }
+
+        if self.bloxroute_auth.is_some() && chain_id != 1 {
+            return Err(ConfigError::Invalid {
+                key: "bloxroute_auth",
+                value: "<hidden>".into(),
+                reason: format!("bloXroute relay isn't supported on chain {chain_id}"),
+            });
+        }
+
+        let queue_capacity = self.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+        if queue_capacity == 0 {
+            return Err(ConfigError::Invalid {
+                key: "queue_capacity",
+                value: queue_capacity.to_string(),
+                reason: "Expect at least 1".into(),
+            });
+        }
+
+        let replay_depth = self.replay_depth.unwrap_or_else(|| vec![0, 1]);
+        if replay_depth.is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "replay_depth",
+                value: String::new(),
+                reason: "Expect at least one depth".into(),
+            });
+        }
+
+        Ok(Config {
+            http_rpc_url: parse_url(
+                "http_rpc_url",
+                &self
+                    .http_rpc_url
+                    .ok_or(ConfigError::Missing("http_rpc_url"))?,
+            )?,
+            wss_rpc_url: parse_url(
+                "wss_rpc_url",
+                &self
+                    .wss_rpc_url
+                    .ok_or(ConfigError::Missing("wss_rpc_url"))?,
+            )?,
+            mempool_sources: self
+                .mempool_sources
+                .unwrap_or_default()
+                .iter()
+                .map(|source| parse_url("mempool_sources", source))
+                .collect::<Result<_, _>>()?,
+            chain_id,
+            contract: parse(
+                "contract",
+                &self.contract.ok_or(ConfigError::Missing("contract"))?,
+            )?,
+            wallet,
+            relay_signer,
+            max_concurrent: match self.max_concurrent.unwrap_or(1) {
+                0 => None,
+                max_concurrent => Some(max_concurrent),
+            },
+            queue_capacity,
+            uncle_protect: self.uncle_protect.unwrap_or(true),
+            max_block: self.max_block.unwrap_or(3),
+            priority: parse_priority(self.priority.as_deref().unwrap_or("none"))?,
+            mode: match self.mode.as_deref().unwrap_or("frontrun") {
+                "frontrun" => Mode::Frontrun,
+                "backrun" => Mode::Backrun,
+                "sandwich" => Mode::Sandwich,
+                mode => {
+                    return Err(ConfigError::Invalid {
+                        key: "mode",
+                        value: mode.into(),
+                        reason: "Expect `frontrun`, `backrun` or `sandwich`".into(),
+                    })
+                }
+            },
+            backend: match self.backend.as_deref().unwrap_or("parity") {
+                "parity" => Backend::Parity,
+                "geth" => Backend::Geth,
+                "evm" => Backend::Evm,
+                backend => {
+                    return Err(ConfigError::Invalid {
+                        key: "backend",
+                        value: backend.into(),
+                        reason: "Expect `parity`, `geth` or `evm`".into(),
+                    })
+                }
+            },
+            relays: relays
+                .iter()
+                .map(|relay| parse_url("relays", relay))
+                .collect::<Result<_, _>>()?,
+            simulation_check: SimulationCheck {
+                min_coinbase_diff: parse_wei(
+                    "min_coinbase_diff",
+                    self.min_coinbase_diff.as_deref().unwrap_or("0"),
+                )?,
+                min_effective_gas_price: parse_wei(
+                    "min_gas_price",
+                    self.min_gas_price.as_deref().unwrap_or("0"),
+                )?,
+                max_gas_used: self.max_gas_used.map(U256::from),
+            },
+            analysis: self.analysis,
+            replay_depth,
+            bloxroute_auth: self.bloxroute_auth,
+            flash_lenders: match self.flash_lenders {
+                Some(lenders) => lenders
+                    .iter()
+                    .map(|lender| parse("flash_lenders", lender))
+                    .collect::<Result<_, _>>()?,
+                None => flash_lender_list(chain_id),
+            },
+        })
+    }
+}
+
+impl Config {
+    // Load from the environment variables, the config file and the command line args in order.
+    pub fn load<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ConfigError> {
+        let (path, args) = RawConfig::from_args(args)?;
+        let file = match path {
+            Some(path) => RawConfig::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                RawConfig::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => RawConfig::default(),
+        };
+
+        RawConfig::from_env()?.merge(file).merge(args).build()
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|error: T::Err| ConfigError::Invalid {
+            key,
+            value: value.into(),
+            reason: error.to_string(),
+        })
+}
+
+fn parse_private_key(key: &'static str, value: &str) -> Result<LocalWallet, ConfigError> {
+    value
+        .trim_start_matches("0x")
+        .parse::<LocalWallet>()
+        .map_err(|error| ConfigError::Invalid {
+            key,
+            // Never print the private key.
+            value: "<hidden>".into(),
+            reason: error.to_string(),
+        })
+}
+
+// `U256::from_str` parses hex, the amount is in decimal wei.
+fn parse_wei(key: &'static str, value: &str) -> Result<U256, ConfigError> {
+    U256::from_dec_str(value.trim()).map_err(|error| ConfigError::Invalid {
+        key,
+        value: value.into(),
+        reason: error.to_string(),
+    })
+}
+
+fn parse_url(key: &'static str, value: &str) -> Result<Url, ConfigError> {
+    parse(key, value)
+}
+
+fn parse_priority(value: &str) -> Result<Priority, ConfigError> {
+    let invalid = |reason: &str| ConfigError::Invalid {
+        key: "priority",
+        value: value.into(),
+        reason: reason.into(),
+    };
+    let parse_wei = |wei: &str| {
+        U256::from_dec_str(wei).map_err(|_| invalid("Expect the amount in wei, e.g. `fixed:1000`"))
+    };
+    let parse_percent = |percent: &str| {
+        percent
+            .parse::<u64>()
+            .ok()
+            .filter(|percent| *percent <= 100)
+            .ok_or_else(|| invalid("Expect the percent in 0..=100, e.g. `share:70`"))
+    };
+
+    match value.split(':').collect::<Vec<_>>()[..] {
+        ["none"] => Ok(Priority::None),
+        ["fixed", fee] => Ok(Priority::Fixed(parse_wei(fee)?)),
+        ["share", percent] => Ok(Priority::Share {
+            percent: parse_percent(percent)?,
+            cap: None,
+        }),
+        ["share", percent, cap] => Ok(Priority::Share {
+            percent: parse_percent(percent)?,
+            cap: Some(parse_wei(cap)?),
+        }),
+        ["margin", margin] => Ok(Priority::Margin(parse_wei(margin)?)),
+        ["competitive", step_percent, percent] => Ok(Priority::Competitive {
+            step_percent: parse_percent(step_percent)?,
+            percent: parse_percent(percent)?,
+        }),
+        _ => Err(invalid(PRIORITY_SPEC)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_priority, Backend, ConfigError, Mode, Priority, RawConfig};
+    use crate::utils::BribeContext;
+    use ethers::prelude::*;
+
+    const PRIVATE_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn args_override_config_file() {
+        let file: RawConfig = toml::from_str(&format!(
+            r#"
+            http_rpc_url = "http://localhost:8545"
+            wss_rpc_url = "ws://localhost:8546"
+            chain_id = 1
+            contract = "0x0000000000000000000000000000000000000001"
+            private_key = "{PRIVATE_KEY}"
+            max_concurrent = 4
+            priority = "share:70:12365048376181357"
+            analysis = ["eth"]
+            "#
+        ))
+        .unwrap();
+        let (_, args) = RawConfig::from_args(to_args(&[
+            "--max-concurrent",
+            "0",
+            "--backend=evm",
+            "--mode=backrun",
+            "--min-coinbase-diff",
+            "1000",
+            "--relay",
+            "https://relay.flashbots.net",
+            "--replay-depth",
+            "1,2",
+            "--mempool-source",
+            "wss://eth.llamarpc.com",
+            "--mempool-source=ws://localhost:8547",
+            "--queue-capacity",
+            "64",
+            "--flash-lender",
+            "0x0000000000000000000000000000000000000002",
+        ]))
+        .unwrap();
+
+        let config = file.merge(args).build().unwrap();
+        assert_eq!(config.max_concurrent, None);
+        assert_eq!(config.backend, Backend::Evm);
+        assert_eq!(config.mode, Mode::Backrun);
+        assert_eq!(config.simulation_check.min_coinbase_diff, U256::from(1000));
+        assert_eq!(config.simulation_check.max_gas_used, None);
+        assert_eq!(config.relays.len(), 1);
+        assert_eq!(config.analysis, Some(vec!["eth".to_string()]));
+        assert_eq!(config.replay_depth, vec![1, 2]);
+        assert_eq!(config.mempool_sources.len(), 2);
+        assert_eq!(config.queue_capacity, 64);
+        assert_eq!(config.flash_lenders, vec![Address::from_low_u64_be(2)]);
+        assert_eq!(
+            config
+                .priority
+                .to_policy()
+                .bribe(&BribeContext::new(U256::exp10(18))),
+            Some(12365048376181357_u64.into())
+        );
+        assert!(config.uncle_protect);
+    }
+
+    #[tokio::test]
+    async fn invalid_config_error_message() {
+        assert!(matches!(
+            RawConfig::from_args(to_args(&["--chain-id", "mainnet"])),
+            Err(ConfigError::Invalid {
+                key: "chain_id",
+                ..
+            })
+        ));
+        assert!(matches!(
+            RawConfig::from_args(to_args(&["--unknown", "1"])),
+            Err(ConfigError::UnknownFlag(_))
+        ));
+
+        let error = RawConfig::default().build().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Missing config <private_key>, set `private_key` in the config file, flag --private-key or environment variable PRIVATE_KEY"
+        );
+
+        let (_, args) = RawConfig::from_args(to_args(&[
+            "--private-key",
+            PRIVATE_KEY,
+            "--relay-private-key",
+            PRIVATE_KEY,
+        ]))
+        .unwrap();
+        assert!(matches!(
+            args.build(),
+            Err(ConfigError::Invalid {
+                key: "relay_private_key",
+                ..
+            })
+        ));
+
+        assert_eq!(parse_priority("none").unwrap(), Priority::None);
+        assert!(parse_priority("share:101").is_err());
+        assert!(parse_priority("fixed:0x10").is_err());
+        assert_eq!(
+            parse_priority("competitive:10:50").unwrap(),
+            Priority::Competitive {
+                step_percent: 10,
+                percent: 50
+            }
+        );
+    }
+}
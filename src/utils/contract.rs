@@ -1,37 +1,57 @@
-use ethers::prelude::*;
+use crate::utils::{ArbitrageError, BribeContext, BribePolicy, BundleTx, FlashBotUtil};
 use ethers::types::transaction::eip2718::TypedTransaction;
-use std::error::Error;
+use ethers::{abi::AbiDecode, prelude::*};
 use std::ops::Deref;
 use std::sync::Arc;
 
 abigen!(ArbitrageContract, "out/Arbitrage.sol/Arbitrage.json");
+abigen!(ERC20Token, "out/Arbitrage.sol/IERC20.json");
+abigen!(
+    IERC3156FlashLender,
+    "out/Arbitrage.sol/IERC3156FlashLender.json"
+);
 
-pub struct ArbitrageUtil<'a, M, S> {
-    inner: ArbitrageContract<&'a SignerMiddleware<M, S>>,
-    client: &'a SignerMiddleware<M, S>,
+// `flashLender` is declared after `owner` of `Owned`.
+const FLASH_LENDER_SLOT: u64 = 1;
+// The gas limit of the tx depending on the former ones of the bundle, it can't be estimated alone,
+// e.g. the `run` tx after `setFlashLender`. Only the gas used is paid, but the signer needs the balance of the limit.
+// It's only used if the relay simulation fails, see `with_dependent_gas`.
+pub const DEPENDENT_TX_GAS: u64 = 3_000_000;
+// The gas limit over the gas used in the relay simulation in percent,
+// e.g. the gas kept by the 63/64 rule, the refund and the coinbase transfer of the bribe.
+const DEPENDENT_TX_GAS_MARGIN: u64 = 130;
+
+pub struct ArbitrageUtil<M, S> {
+    inner: ArbitrageContract<SignerMiddleware<M, S>>,
+    client: Arc<SignerMiddleware<M, S>>,
 }
 
-impl<'a, M, S> Deref for ArbitrageUtil<'a, M, S> {
-    type Target = ArbitrageContract<&'a SignerMiddleware<M, S>>;
+impl<M, S> Deref for ArbitrageUtil<M, S> {
+    type Target = ArbitrageContract<SignerMiddleware<M, S>>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
     }
 }
 
-impl<'a, M: Middleware, S: Signer> ArbitrageUtil<'a, M, S> {
-    pub fn init(client: &'a SignerMiddleware<M, S>, contract: Address) -> Self {
+// Not derived, the middleware needn't be `Clone` to share the client.
+impl<M: Middleware + 'static, S: Signer + 'static> Clone for ArbitrageUtil<M, S> {
+    fn clone(&self) -> Self {
+        Self::init(self.client.clone(), self.address())
+    }
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> ArbitrageUtil<M, S> {
+    pub fn init(client: Arc<SignerMiddleware<M, S>>, contract: Address) -> Self {
         Self {
-            inner: ArbitrageContract::new(contract, Arc::new(client)),
+            inner: ArbitrageContract::new(contract, client.clone()),
             client,
         }
     }
 
-    pub async fn deploy(
-        client: &'a SignerMiddleware<M, S>,
-    ) -> Result<ArbitrageUtil<'a, M, S>, Box<dyn Error + 'a>> {
+    pub async fn deploy(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError> {
         Ok(Self {
-            inner: ArbitrageContract::deploy(Arc::new(client.clone()), ())?
+            inner: ArbitrageContract::deploy(client.clone(), ())?
                 .send()
                 .await?,
             client,
@@ -43,45 +63,223 @@ impl<'a, M: Middleware, S: Signer> ArbitrageUtil<'a, M, S> {
         tx_list: Vec<T>,
         uncle_protect: bool,
         priority: Option<U256>,
-    ) -> Result<TypedTransaction, Box<dyn Error + 'a>> {
+    ) -> Result<TypedTransaction, ArbitrageError> {
         Ok(self
             .run(self.parse_tx_list(tx_list, uncle_protect, priority).await?)
             .from(self.client().address())
             .tx)
     }
 
-    async fn parse_tx_list<T: Into<TypedTransaction>>(
+    // The bundle of `to_tx`, set the flash lender first if the tx list borrows from another lender.
+    // The gas limit of the `run` tx after `setFlashLender` is simulated by the relay of `flashbot`.
+    pub async fn to_tx_bundle<T: Into<TypedTransaction>>(
+        &self,
+        tx_list: Vec<T>,
+        uncle_protect: bool,
+        priority: Option<U256>,
+        flashbot: &FlashBotUtil,
+    ) -> Result<Vec<BundleTx>, ArbitrageError> {
+        let tx_list = tx_list
+            .into_iter()
+            .map(|tx| tx.into())
+            .collect::<Vec<TypedTransaction>>();
+
+        let mut bundle = Vec::from_iter(self.to_lender_tx(&tx_list).await?.map(BundleTx::from));
+        bundle.push(self.to_tx(tx_list, uncle_protect, priority).await?.into());
+        if bundle.len() == 1 {
+            return Ok(bundle);
+        }
+
+        Ok(self.with_dependent_gas(bundle, &[1], flashbot).await)
+    }
+
+    // The bundle of `to_tx_bundle` paying the bribe of the policy, none if the policy gives up.
+    // The gas cost is simulated over the whole bundle by the relay of `flashbot`.
+    pub async fn to_bribe_bundle<T: Into<TypedTransaction> + Clone>(
         &self,
         tx_list: Vec<T>,
         uncle_protect: bool,
+        profit: U256,
+        policy: &dyn BribePolicy,
+        flashbot: &FlashBotUtil,
+    ) -> Result<Option<Vec<BundleTx>>, ArbitrageError> {
+        let bundle = self
+            .to_tx_bundle(tx_list.clone(), uncle_protect, None, flashbot)
+            .await?;
+        let context = BribeContext::init(flashbot, profit, bundle).await?;
+        match policy.bribe(&context) {
+            Some(priority) => Ok(Some(
+                self.to_tx_bundle(tx_list, uncle_protect, Some(priority), flashbot)
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // The sandwich bundle of [front tx, victim tx, back tx], the income is checked over the whole sequence.
+    // The flash lender is set first if the back leg borrows from another lender.
+    // The front leg can't borrow, `onFlashLoan` checks the income within the loan but the front leg always loses.
+    // The gas limit of the back tx is simulated by the relay of `flashbot`, it reverts without the front tx.
+    // @param victim The signed RLP of the victim tx, see `FlashBotUtil::get_raw_transaction`
+    // @param priority The fee sent to coinbase by the back tx
+    pub async fn to_sandwich_bundle<T: Into<TypedTransaction>>(
+        &self,
+        front: Vec<T>,
+        victim: Bytes,
+        back: Vec<T>,
+        uncle_protect: bool,
         priority: Option<U256>,
-    ) -> Result<Bytes, Box<dyn Error + 'a>> {
-        let mut call_list = Vec::new();
-        for tx in tx_list {
-            let tx: TypedTransaction = tx.into();
-            call_list.push(abi::Token::Bytes(abi::encode(&[
-                abi::Token::Address(*tx.to_addr().unwrap_or(&Address::zero())),
-                abi::Token::Uint(*tx.value().unwrap_or(&U256::zero())),
-                abi::Token::Bytes(tx.data().unwrap_or(&Bytes::from(vec![0])).to_vec()),
-            ])));
+        flashbot: &FlashBotUtil,
+    ) -> Result<Vec<BundleTx>, ArbitrageError> {
+        let front = front
+            .into_iter()
+            .map(|tx| tx.into())
+            .collect::<Vec<TypedTransaction>>();
+        let back = back
+            .into_iter()
+            .map(|tx| tx.into())
+            .collect::<Vec<TypedTransaction>>();
+        if front
+            .iter()
+            .filter_map(|tx| tx.data())
+            .any(|data| FlashLoanCall::decode(data).is_ok())
+        {
+            return Err(ArbitrageError::bundle(
+                "The front leg of the sandwich can't borrow a flash loan",
+            ));
+        }
+
+        let mut bundle = Vec::from_iter(self.to_lender_tx(&back).await?.map(BundleTx::from));
+        let front = self
+            .run_front(self.parse_tx_list(front, uncle_protect, None).await?)
+            .from(self.client().address())
+            .tx;
+        let back = self
+            .run_back(self.parse_tx_list(back, uncle_protect, priority).await?)
+            .from(self.client().address())
+            .tx;
+        bundle.extend([front.into(), BundleTx::Signed(victim), back.into()]);
+
+        let back_index = bundle.len() - 1;
+        Ok(self
+            .with_dependent_gas(bundle, &[back_index], flashbot)
+            .await)
+    }
+
+    // Set the gas limit of the dependent txs by the relay simulation of the whole bundle, with the margin.
+    // `DEPENDENT_TX_GAS` is kept if the simulation fails, e.g. the relay is down, the bundle is checked again before sending.
+    // @param dependent The index of the dependent txs in the bundle
+    async fn with_dependent_gas(
+        &self,
+        mut bundle: Vec<BundleTx>,
+        dependent: &[usize],
+        flashbot: &FlashBotUtil,
+    ) -> Vec<BundleTx> {
+        for index in dependent {
+            if let Some(BundleTx::Unsigned(tx)) = bundle.get_mut(*index) {
+                tx.set_gas(DEPENDENT_TX_GAS);
+            }
+        }
+        let gas_list = match flashbot.estimate_gas_list(bundle.clone()).await {
+            Ok(gas_list) => gas_list,
+            Err(_) => return bundle,
+        };
+        for index in dependent {
+            if let (Some(BundleTx::Unsigned(tx)), Some(gas_used)) =
+                (bundle.get_mut(*index), gas_list.get(*index))
+            {
+                tx.set_gas(*gas_used * DEPENDENT_TX_GAS_MARGIN / 100);
+            }
+        }
+
+        bundle
+    }
+
+    // Set the flash lender if the tx list borrows from another lender.
+    // @return None if the tx list doesn't borrow or the lender is already set
+    async fn to_lender_tx(
+        &self,
+        tx_list: &[TypedTransaction],
+    ) -> Result<Option<TypedTransaction>, ArbitrageError> {
+        let mut lender_list = tx_list.iter().filter_map(|tx| {
+            let call = FlashLoanCall::decode(tx.data()?).ok()?;
+            (call.receiver == self.address()).then_some(*tx.to_addr()?)
+        });
+        let lender = match lender_list.next() {
+            Some(lender) => lender,
+            None => return Ok(None),
+        };
+        if lender_list.any(|other| other != lender) {
+            return Err(ArbitrageError::bundle(
+                "Only one flash lender is supported in a tx list",
+            ));
         }
 
+        let current_lender = self
+            .client
+            .get_storage_at(
+                self.address(),
+                H256::from_low_u64_be(FLASH_LENDER_SLOT),
+                None,
+            )
+            .await?;
+        if Address::from(current_lender) == lender {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.set_flash_lender(lender)
+                .from(self.client().address())
+                .tx,
+        ))
+    }
+
+    async fn parse_tx_list<T: Into<TypedTransaction>>(
+        &self,
+        tx_list: Vec<T>,
+        uncle_protect: bool,
+        priority: Option<U256>,
+    ) -> Result<Bytes, ArbitrageError> {
         let block_hash = if uncle_protect {
             let last_block_number = self.client.get_block_number().await?;
             let block = self
                 .client
                 .get_block(last_block_number)
                 .await?
-                .ok_or("Get block number error")?;
+                .ok_or_else(|| ArbitrageError::rpc("Get block number error"))?;
             block.hash.unwrap()
         } else {
             TxHash::zero()
         };
-        Ok(abi::encode(&[
-            abi::Token::FixedBytes((*block_hash.as_fixed_bytes()).into()),
-            abi::Token::Uint(priority.unwrap_or_default()),
-            abi::Token::Array(call_list),
-        ])
-        .into())
+        Ok(encode_multicall(
+            tx_list,
+            block_hash,
+            priority.unwrap_or_default(),
+        ))
+    }
+}
+
+// Encode the data of `Arbitrage.run` and `Arbitrage.onFlashLoan`.
+// @param block_hash The parent block hash for uncle protection, zero to skip the check
+// @param priority The fee sent to coinbase
+pub fn encode_multicall<T: Into<TypedTransaction>>(
+    tx_list: Vec<T>,
+    block_hash: H256,
+    priority: U256,
+) -> Bytes {
+    let mut call_list = Vec::new();
+    for tx in tx_list {
+        let tx: TypedTransaction = tx.into();
+        call_list.push(abi::Token::Bytes(abi::encode(&[
+            abi::Token::Address(*tx.to_addr().unwrap_or(&Address::zero())),
+            abi::Token::Uint(*tx.value().unwrap_or(&U256::zero())),
+            abi::Token::Bytes(tx.data().unwrap_or(&Bytes::from(vec![0])).to_vec()),
+        ])));
     }
+
+    abi::encode(&[
+        abi::Token::FixedBytes((*block_hash.as_fixed_bytes()).into()),
+        abi::Token::Uint(priority),
+        abi::Token::Array(call_list),
+    ])
+    .into()
 }
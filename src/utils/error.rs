@@ -0,0 +1,334 @@
+use crate::utils::{
+    ArbitrageContractErrors, ConfigError, Rejection, RevertReason, SimulationError,
+};
+use cfmms::errors::CFMMError;
+use ethers::abi::{AbiDecode, AbiError};
+use ethers::middleware::signer::SignerMiddlewareError;
+use ethers::prelude::*;
+use ethers::utils::hex;
+use std::error::Error;
+use std::fmt;
+
+// The custom errors of `contract/Arbitrage.sol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArbitrageRevert {
+    Unauthorized,
+    UncleBlock,
+    SelfCall,
+    SufficientIncome,
+    FlashLenderCall,
+    CoinbaseCall,
+    MissingCheckpoint,
+}
+
+impl ArbitrageRevert {
+    // @return None if the data isn't a custom error of the contract
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Some(match ArbitrageContractErrors::decode(data).ok()? {
+            ArbitrageContractErrors::Unauthorized(_) => Self::Unauthorized,
+            ArbitrageContractErrors::UncleBlock(_) => Self::UncleBlock,
+            ArbitrageContractErrors::SelfCall(_) => Self::SelfCall,
+            ArbitrageContractErrors::SufficientIncome(_) => Self::SufficientIncome,
+            ArbitrageContractErrors::FlashLenderCall(_) => Self::FlashLenderCall,
+            ArbitrageContractErrors::CoinbaseCall(_) => Self::CoinbaseCall,
+            ArbitrageContractErrors::MissingCheckpoint(_) => Self::MissingCheckpoint,
+        })
+    }
+
+    // The income is gone or the block is stale, the others mean the setup is wrong, e.g. another signer.
+    pub fn is_unprofitable(&self) -> bool {
+        matches!(self, Self::SufficientIncome | Self::UncleBlock)
+    }
+}
+
+impl fmt::Display for ArbitrageRevert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// The source of the error, owned and `Send + Sync` to cross tasks and channels.
+pub type ErrorSource = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum ArbitrageError {
+    // The call or the gas estimation reverts, e.g. `SufficientIncome` or the slippage of the pool.
+    Revert(RevertReason),
+    // The node fails or rejects the request.
+    Rpc(ErrorSource),
+    // The contract binding fails to encode or decode, e.g. the ABI mismatches the contract.
+    Abi(ErrorSource),
+    Signer(ErrorSource),
+    // The trace of the tx isn't available, e.g. the node doesn't support the backend.
+    Trace(ErrorSource),
+    // The profit of the simulated state can't be analyzed, e.g. no pool to price the token.
+    Analysis(ErrorSource),
+    // The bundle can't be built, e.g. the tx list borrows from more than one flash lender.
+    Bundle(ErrorSource),
+    Relay(ErrorSource),
+    Simulation(SimulationError),
+    Config(ConfigError),
+    // The prefilter strategy rejects the tx before the simulation.
+    Rejected(Rejection),
+}
+
+impl ArbitrageError {
+    pub fn rpc<E: Into<ErrorSource>>(error: E) -> Self {
+        Self::Rpc(error.into())
+    }
+
+    pub fn trace<E: Into<ErrorSource>>(error: E) -> Self {
+        Self::Trace(error.into())
+    }
+
+    pub fn analysis<E: Into<ErrorSource>>(error: E) -> Self {
+        Self::Analysis(error.into())
+    }
+
+    pub fn bundle<E: Into<ErrorSource>>(error: E) -> Self {
+        Self::Bundle(error.into())
+    }
+
+    // Classify the error of `eth_call` or `eth_estimateGas`, the revert data is kept in the message
+    // of the json rpc error, e.g. `(code: 3, message: execution reverted, data: Some(String("0x...")))`.
+    pub fn from_call<E: fmt::Display + Into<ErrorSource>>(error: E) -> Self {
+        match revert_reason(&error.to_string()) {
+            Some(reason) => Self::Revert(reason),
+            None => Self::Rpc(error.into()),
+        }
+    }
+
+    // The opportunity is gone, e.g. the state is changed by another tx, retry with the next tx.
+    // The others mean the infrastructure or the setup is broken, e.g. the node, the relay or the config.
+    pub fn is_unprofitable(&self) -> bool {
+        match self {
+            Self::Revert(reason) => reason.is_unprofitable(),
+            Self::Simulation(SimulationError::Reverted { reason, .. }) => reason.is_unprofitable(),
+            Self::Simulation(SimulationError::Relay(_)) => false,
+            // The coinbase payment, effective gas price or gas used check.
+            Self::Simulation(_) => true,
+            Self::Rejected(_) => true,
+            Self::Rpc(_)
+            | Self::Abi(_)
+            | Self::Signer(_)
+            | Self::Trace(_)
+            | Self::Analysis(_)
+            | Self::Bundle(_)
+            | Self::Relay(_)
+            | Self::Config(_) => false,
+        }
+    }
+}
+
+// Only the `data` of the json rpc error is decoded, the other hex words of the message may be an address or a tx hash.
+// @return The raw message if the data is missing or unknown
+fn revert_reason(message: &str) -> Option<RevertReason> {
+    if !message.contains("revert") {
+        return None;
+    }
+    let data = message
+        .split_once("data: Some(String(\"")
+        .and_then(|(_, data)| data.split_once('"'))
+        .and_then(|(data, _)| hex::decode(data.strip_prefix("0x")?).ok());
+    Some(
+        data.and_then(|data| RevertReason::from_data(&data))
+            .unwrap_or_else(|| RevertReason::Other(message.into())),
+    )
+}
+
+impl fmt::Display for ArbitrageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Revert(reason) => write!(f, "Reverted: {reason}"),
+            Self::Rpc(error) => write!(f, "RPC error: {error}"),
+            Self::Abi(error) => write!(f, "ABI error: {error}"),
+            Self::Signer(error) => write!(f, "Signer error: {error}"),
+            Self::Trace(error) => write!(f, "Trace error: {error}"),
+            Self::Analysis(error) => write!(f, "Analysis error: {error}"),
+            Self::Bundle(error) => write!(f, "Bundle error: {error}"),
+            Self::Relay(error) => write!(f, "Relay error: {error}"),
+            Self::Simulation(error) => write!(f, "{error}"),
+            Self::Config(error) => write!(f, "{error}"),
+            Self::Rejected(rejection) => write!(f, "{rejection}"),
+        }
+    }
+}
+
+impl Error for ArbitrageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Revert(_) => None,
+            Self::Rpc(error)
+            | Self::Abi(error)
+            | Self::Signer(error)
+            | Self::Trace(error)
+            | Self::Analysis(error)
+            | Self::Bundle(error)
+            | Self::Relay(error) => Some(error.as_ref()),
+            Self::Simulation(error) => Some(error),
+            Self::Config(error) => Some(error),
+            Self::Rejected(rejection) => Some(rejection),
+        }
+    }
+}
+
+impl From<ProviderError> for ArbitrageError {
+    fn from(error: ProviderError) -> Self {
+        match revert_reason(&error.to_string()) {
+            Some(reason) => Self::Revert(reason),
+            None => Self::Rpc(Box::new(error)),
+        }
+    }
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> From<SignerMiddlewareError<M, S>>
+    for ArbitrageError
+{
+    fn from(error: SignerMiddlewareError<M, S>) -> Self {
+        Self::from_call(error)
+    }
+}
+
+impl<M: Middleware + 'static> From<ContractError<M>> for ArbitrageError {
+    fn from(error: ContractError<M>) -> Self {
+        match error {
+            ContractError::MiddlewareError(_) | ContractError::ProviderError(_) => {
+                Self::from_call(error)
+            }
+            error => Self::Abi(Box::new(error)),
+        }
+    }
+}
+
+impl<M: Middleware + 'static> From<CFMMError<M>> for ArbitrageError {
+    fn from(error: CFMMError<M>) -> Self {
+        Self::from_call(error)
+    }
+}
+
+impl From<AbiError> for ArbitrageError {
+    fn from(error: AbiError) -> Self {
+        Self::Abi(Box::new(error))
+    }
+}
+
+impl From<WalletError> for ArbitrageError {
+    fn from(error: WalletError) -> Self {
+        Self::Signer(Box::new(error))
+    }
+}
+
+impl From<SimulationError> for ArbitrageError {
+    fn from(error: SimulationError) -> Self {
+        Self::Simulation(error)
+    }
+}
+
+impl From<ConfigError> for ArbitrageError {
+    fn from(error: ConfigError) -> Self {
+        Self::Config(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArbitrageError, ArbitrageRevert};
+    use crate::utils::{
+        ArbitrageContractErrors, Rejection, RevertReason, SelfCall, SimulationError, Unauthorized,
+    };
+    use ethers::abi::AbiEncode;
+    use ethers::prelude::*;
+    use ethers::utils::hex;
+    use std::io;
+
+    #[tokio::test]
+    async fn classify_call_error() {
+        let data = hex::encode(ArbitrageContractErrors::Unauthorized(Unauthorized).encode());
+        let error = ArbitrageError::from_call(format!(
+            "(code: 3, message: execution reverted, data: Some(String(\"0x{data}\")))"
+        ));
+        assert!(matches!(
+            error,
+            ArbitrageError::Revert(RevertReason::Contract(ArbitrageRevert::Unauthorized))
+        ));
+        // The signer isn't the owner, retrying never helps.
+        assert!(!error.is_unprofitable());
+
+        // The hex word other than the data isn't decoded, e.g. the address starting with a selector.
+        let selector = hex::encode(&ArbitrageContractErrors::SelfCall(SelfCall).encode()[..4]);
+        let error = ArbitrageError::from_call(format!(
+            "(code: 3, message: execution reverted by 0x{selector}{}, data: Some(String(\"0x{data}\")))",
+            "0".repeat(32)
+        ));
+        assert!(matches!(
+            error,
+            ArbitrageError::Revert(RevertReason::Contract(ArbitrageRevert::Unauthorized))
+        ));
+        let message = format!("execution reverted by 0x{selector}{}", "0".repeat(32));
+        assert!(matches!(
+            ArbitrageError::from_call(message.clone()),
+            ArbitrageError::Revert(RevertReason::Other(other)) if other == message
+        ));
+        let error = ArbitrageError::from_call(format!(
+            "(code: 3, message: execution reverted, data: Some(String(\"0x{}\")))",
+            // Error(string)
+            hex::encode(
+                [
+                    vec![0x08, 0xc3, 0x79, 0xa0],
+                    abi::encode(&[abi::Token::String(
+                        "Ownable: caller is not the owner".into()
+                    )])
+                ]
+                .concat()
+            )
+        ));
+        assert!(matches!(
+            error,
+            ArbitrageError::Revert(RevertReason::Message(message)) if message == "Ownable: caller is not the owner"
+        ));
+
+        let error = ArbitrageError::Simulation(SimulationError::Reverted {
+            tx_hash: TxHash::zero(),
+            reason: RevertReason::Contract(ArbitrageRevert::SufficientIncome),
+        });
+        assert!(error.is_unprofitable());
+
+        let error = ArbitrageError::from_call("error sending request for url");
+        assert!(matches!(error, ArbitrageError::Rpc(_)));
+        assert!(!error.is_unprofitable());
+
+        // The rejected tx is skipped, the strategy and the reason are kept.
+        let error = ArbitrageError::Rejected(Rejection {
+            strategy: "transfer".into(),
+            reason: "Native token transfer".into(),
+        });
+        assert!(error.is_unprofitable());
+        assert_eq!(
+            error.to_string(),
+            "Rejected by strategy <transfer>: Native token transfer"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_error_across_task() {
+        let error = tokio::spawn(async { ArbitrageError::rpc("connection refused") })
+            .await
+            .unwrap();
+        assert_eq!(error.to_string(), "RPC error: connection refused");
+        assert_eq!(
+            std::error::Error::source(&error).unwrap().to_string(),
+            "connection refused"
+        );
+
+        // The wrapped error is kept as the source, not only its message.
+        let error = ArbitrageError::from_call(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "connection refused",
+        ));
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(
+            source.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+}
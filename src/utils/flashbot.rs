@@ -1,15 +1,79 @@
-use ethers::core::rand::thread_rng;
+use crate::utils::{
+    relay_list, ArbitrageError, BundleRelay, FlashbotsRelay, RelayList, RelayResult, RelaySigner,
+    SimulationCheck, SimulationError, SimulationReport,
+};
 use ethers::prelude::*;
 use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::keccak256;
 use ethers_flashbots::*;
-use std::error::Error;
+use std::future::Future;
 use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 type Singer = SignerMiddleware<FlashbotsMiddleware<Provider<Http>, LocalWallet>, LocalWallet>;
 
+// The tx of bundle, our tx is signed when the bundle is built, the others are already signed.
+#[derive(Debug, Clone)]
+pub enum BundleTx {
+    Unsigned(Box<TypedTransaction>),
+    Signed(Bytes),
+}
+
+impl From<TypedTransaction> for BundleTx {
+    fn from(tx: TypedTransaction) -> Self {
+        Self::Unsigned(Box::new(tx))
+    }
+}
+
+impl From<TransactionRequest> for BundleTx {
+    fn from(tx: TransactionRequest) -> Self {
+        Self::Unsigned(Box::new(tx.into()))
+    }
+}
+
+impl From<Eip1559TransactionRequest> for BundleTx {
+    fn from(tx: Eip1559TransactionRequest) -> Self {
+        Self::Unsigned(Box::new(tx.into()))
+    }
+}
+
+#[derive(Debug)]
+pub enum BundleOutcome {
+    Included { block: U64, tx_hash: TxHash },
+    // The target tx is mined by others.
+    TargetMined { block: U64 },
+    // The nonce is used by another tx of the signer.
+    NonceInvalidated,
+    // The bribe policy gives up.
+    Unprofitable,
+    SimulationFailed(SimulationError),
+    // Not included in `max_block` blocks.
+    Expired,
+}
+
+#[derive(Debug)]
+pub struct BundleAttempt {
+    pub block: U64,
+    pub report: SimulationReport,
+    pub result_list: Vec<RelayResult>,
+}
+
+#[derive(Debug)]
+pub struct BundleSubmission {
+    pub outcome: BundleOutcome,
+    pub attempt_list: Vec<BundleAttempt>,
+}
+
 pub struct FlashBotUtil {
-    pub inner: Singer,
+    pub inner: Arc<Singer>,
+    relay_signer: RelaySigner,
+    simulation_relay: FlashbotsRelay,
+    simulation_check: SimulationCheck,
+    relay_list: RelayList,
 }
 
 impl Deref for FlashBotUtil {
@@ -21,48 +85,303 @@ impl Deref for FlashBotUtil {
 }
 
 impl FlashBotUtil {
+    // The relay signer is random, see `init_with_relay_list` to build the reputation.
     pub fn init(provider: Provider<Http>, wallet: LocalWallet) -> Option<Self> {
-        if let Some(endpoint) = match wallet.chain_id() {
-            1 => Some("https://relay.flashbots.net"),
-            5 => Some("https://relay-goerli.flashbots.net"),
-            _ => None,
-        } {
-            let flashbot = SignerMiddleware::new(
-                FlashbotsMiddleware::new(
-                    provider,
-                    Url::parse(endpoint).unwrap(),
-                    LocalWallet::new(&mut thread_rng()),
-                ),
-                wallet,
-            );
-            return Some(Self { inner: flashbot });
+        let relay_list = relay_list(wallet.chain_id());
+        Self::init_with_relay_list(provider, wallet, relay_list, RelaySigner::random())
+    }
+
+    // The bundle is simulated by the first relay and sent to all relays.
+    // All relays see the same searcher identity of `relay_signer`, rotating it takes effect on the next bundle.
+    // @return None if the relay list is empty
+    pub fn init_with_relay_list(
+        provider: Provider<Http>,
+        wallet: LocalWallet,
+        relay_list: Vec<Url>,
+        relay_signer: RelaySigner,
+    ) -> Option<Self> {
+        let simulation_relay = relay_list.first()?.clone();
+        // The bundle methods of `FlashbotsMiddleware` keep the initial relay signer, use `run` instead.
+        let flashbot = SignerMiddleware::new(
+            FlashbotsMiddleware::new(provider, simulation_relay.clone(), relay_signer.wallet()),
+            wallet,
+        );
+
+        let mut list = RelayList::default();
+        for relay in relay_list {
+            list.push(Box::new(FlashbotsRelay::new(relay, relay_signer.clone())));
         }
+        Some(Self {
+            inner: Arc::new(flashbot),
+            simulation_relay: FlashbotsRelay::new(simulation_relay, relay_signer.clone()),
+            simulation_check: SimulationCheck::default(),
+            relay_signer,
+            relay_list: list,
+        })
+    }
+
+    // Add the relay with another API, e.g. `BloxrouteRelay`.
+    pub fn with_relay(mut self, relay: Box<dyn BundleRelay>) -> Self {
+        self.relay_list.push(relay);
+        self
+    }
+
+    // Checked before sending, any revert fails the bundle by default.
+    pub fn with_simulation_check(mut self, simulation_check: SimulationCheck) -> Self {
+        self.simulation_check = simulation_check;
+        self
+    }
+
+    // The shared signer client, e.g. for `Simulate` and `ArbitrageUtil`.
+    pub fn client(&self) -> Arc<Singer> {
+        self.inner.clone()
+    }
 
-        None
+    pub fn relay_list(&self) -> &RelayList {
+        &self.relay_list
     }
 
-    pub async fn run<T: Into<TypedTransaction>>(
+    pub fn relay_signer(&self) -> &RelaySigner {
+        &self.relay_signer
+    }
+
+    // @return The simulation report and the result of each relay, the bundle isn't sent if the simulation fails
+    pub async fn run<T: Into<BundleTx>>(
         &self,
         tx_list: Vec<T>,
-    ) -> Result<TxHash, Box<dyn Error>> {
+    ) -> Result<BundleAttempt, ArbitrageError> {
         let bundle = self.to_bundle(tx_list).await?;
-        self.inner().simulate_bundle(&bundle).await?;
-        Ok(self.inner().send_bundle(&bundle).await?.await?)
+        self.simulate_and_send(&bundle)
+            .await
+            .map_err(ArbitrageError::Simulation)
+    }
+
+    // Simulate the bundle by the first relay without checking the result.
+    pub async fn simulate(
+        &self,
+        bundle: &BundleRequest,
+    ) -> Result<SimulationReport, SimulationError> {
+        self.simulation_relay
+            .simulate_bundle(bundle)
+            .await
+            .map(SimulationReport::from)
+            .map_err(SimulationError::Relay)
+    }
+
+    // The gas used by our txs in the relay simulation, the txs signed by others aren't paid by us.
+    // The txs are simulated in sequence, a tx can't be estimated alone if it depends on the former ones.
+    // The revert fails the estimate, e.g. `SufficientIncome` if the opportunity is gone.
+    pub async fn estimate_gas<T: Into<BundleTx>>(
+        &self,
+        tx_list: Vec<T>,
+    ) -> Result<U256, ArbitrageError> {
+        let tx_list = tx_list
+            .into_iter()
+            .map(|tx| tx.into())
+            .collect::<Vec<BundleTx>>();
+        let other_list = tx_list
+            .iter()
+            .filter_map(|tx| match tx {
+                BundleTx::Signed(tx) => Some(H256::from(keccak256(tx))),
+                BundleTx::Unsigned(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        let bundle = self.to_bundle(tx_list).await?;
+        let report = self.simulate(&bundle).await?;
+        // Only the revert is checked, the bundle without bribe pays nothing to coinbase.
+        SimulationCheck::default().check(&report)?;
+        Ok(report.gas_used_without(&other_list))
+    }
+
+    // The gas used by each tx in the relay simulation in order, e.g. to set the gas limit of the dependent tx.
+    // The revert fails the estimate.
+    pub async fn estimate_gas_list<T: Into<BundleTx>>(
+        &self,
+        tx_list: Vec<T>,
+    ) -> Result<Vec<U256>, ArbitrageError> {
+        let bundle = self.to_bundle(tx_list).await?;
+        let report = self.simulate(&bundle).await?;
+        SimulationCheck::default().check(&report)?;
+        Ok(report.tx_list.iter().map(|tx| tx.gas_used).collect())
+    }
+
+    // Resubmit the bundle for the next `max_block` blocks until it's included, the target tx is mined or the nonce is used.
+    // `build` rebuilds the tx list on top of the latest block, e.g. the uncle protection and bribe change with the block,
+    // none to give up. Each attempt is simulated again before sending, the failed simulation is retried on the next block
+    // unless our contract reverts, e.g. the relay error. It ends with the last simulation error if it never passes again.
+    pub async fn run_until_included<T, F, Fut>(
+        &self,
+        mut build: F,
+        target: Option<TxHash>,
+        max_block: u64,
+    ) -> Result<BundleSubmission, ArbitrageError>
+    where
+        F: FnMut(U64) -> Fut,
+        T: Into<BundleTx>,
+        Fut: Future<Output = Result<Option<Vec<T>>, ArbitrageError>>,
+    {
+        let nonce = self.get_transaction_count(self.address(), None).await?;
+        let mut block = self.get_block_number().await?;
+        let mut hash_list = Vec::new();
+        let mut attempt_list = Vec::new();
+        let mut block_count = 0;
+        let mut last_error = None;
+
+        let outcome = loop {
+            if let Some(outcome) = self.to_outcome(&hash_list, target, nonce).await? {
+                break outcome;
+            }
+            if block_count >= max_block {
+                break match last_error {
+                    Some(error) => BundleOutcome::SimulationFailed(error),
+                    None => BundleOutcome::Expired,
+                };
+            }
+            block_count += 1;
+
+            let tx_list = match build(block).await? {
+                Some(tx_list) => tx_list,
+                None => break BundleOutcome::Unprofitable,
+            };
+            let bundle = self.to_bundle(tx_list).await?;
+            hash_list.extend(bundle.transaction_hashes());
+            match self.simulate_and_send(&bundle).await {
+                Ok(attempt) => {
+                    attempt_list.push(attempt);
+                    last_error = None;
+                }
+                Err(error) if error.is_deterministic() => {
+                    break BundleOutcome::SimulationFailed(error)
+                }
+                Err(error) => last_error = Some(error),
+            }
+
+            block = self.wait_block(block).await?;
+        };
+
+        Ok(BundleSubmission {
+            outcome,
+            attempt_list,
+        })
+    }
+
+    // The signed RLP of the pending tx, e.g. the victim tx of the sandwich bundle.
+    pub async fn get_raw_transaction(&self, tx_hash: TxHash) -> Result<Bytes, ArbitrageError> {
+        let raw: Option<Bytes> = self
+            .provider()
+            .request("eth_getRawTransactionByHash", [tx_hash])
+            .await
+            .unwrap_or_default();
+        if let Some(raw) = raw.filter(|raw| !raw.is_empty()) {
+            return Ok(raw);
+        }
+
+        // Not all nodes support `eth_getRawTransactionByHash`, encode it with the signature instead.
+        let tx = self
+            .get_transaction(tx_hash)
+            .await?
+            .ok_or_else(|| ArbitrageError::rpc("Transaction not found in the mempool"))?;
+        Ok(tx.rlp())
+    }
+
+    async fn simulate_and_send(
+        &self,
+        bundle: &BundleRequest,
+    ) -> Result<BundleAttempt, SimulationError> {
+        let report = self.simulate(bundle).await?;
+        self.simulation_check.check(&report)?;
+        Ok(BundleAttempt {
+            // `to_bundle` always sets the target block.
+            block: bundle.block().unwrap_or_default(),
+            report,
+            result_list: self.relay_list.send_bundle(bundle).await,
+        })
+    }
+
+    // @return None if none of the txs is mined and the nonce is still valid
+    async fn to_outcome(
+        &self,
+        hash_list: &[TxHash],
+        target: Option<TxHash>,
+        nonce: U256,
+    ) -> Result<Option<BundleOutcome>, ArbitrageError> {
+        if let Some(outcome) = self.to_mined_outcome(hash_list, target).await? {
+            return Ok(Some(outcome));
+        }
+        if self.get_transaction_count(self.address(), None).await? != nonce {
+            // Our bundle may be mined between the receipts and the nonce are checked, check the receipts again.
+            return Ok(Some(
+                self.to_mined_outcome(hash_list, target)
+                    .await?
+                    .unwrap_or(BundleOutcome::NonceInvalidated),
+            ));
+        }
+
+        Ok(None)
+    }
+
+    // @return None if neither our tx nor the target tx is mined
+    async fn to_mined_outcome(
+        &self,
+        hash_list: &[TxHash],
+        target: Option<TxHash>,
+    ) -> Result<Option<BundleOutcome>, ArbitrageError> {
+        // The target tx may be in the bundle, e.g. the victim tx, it's mined by others if our tx isn't.
+        for tx_hash in hash_list.iter().filter(|tx_hash| Some(**tx_hash) != target) {
+            if let Some(receipt) = self.get_transaction_receipt(*tx_hash).await? {
+                return Ok(Some(BundleOutcome::Included {
+                    block: receipt.block_number.unwrap_or_default(),
+                    tx_hash: *tx_hash,
+                }));
+            }
+        }
+        if let Some(target) = target {
+            if let Some(receipt) = self.get_transaction_receipt(target).await? {
+                return Ok(Some(BundleOutcome::TargetMined {
+                    block: receipt.block_number.unwrap_or_default(),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn wait_block(&self, block: U64) -> Result<U64, ArbitrageError> {
+        loop {
+            tokio::time::sleep(BLOCK_POLL_INTERVAL).await;
+            let current = self.get_block_number().await?;
+            if current > block {
+                return Ok(current);
+            }
+        }
     }
 
-    async fn to_bundle<T: Into<TypedTransaction>>(
+    async fn to_bundle<T: Into<BundleTx>>(
         &self,
         tx_list: Vec<T>,
-    ) -> Result<BundleRequest, Box<dyn Error>> {
+    ) -> Result<BundleRequest, ArbitrageError> {
         let last_block_number = self.get_block_number().await?;
         let mut bundle = BundleRequest::new()
             .set_block(last_block_number + 1)
             .set_simulation_block(last_block_number)
             .set_simulation_timestamp(0);
 
+        // The pending nonce is the same for all txs in the bundle, increase it manually.
+        let mut nonce = None;
         for tx in tx_list {
-            let mut tx: TypedTransaction = tx.into();
+            let mut tx = match tx.into() {
+                BundleTx::Unsigned(tx) => *tx,
+                BundleTx::Signed(tx) => {
+                    bundle = bundle.push_transaction(tx);
+                    continue;
+                }
+            };
+            if let Some(nonce) = nonce {
+                tx.set_nonce(nonce);
+            }
             self.fill_transaction(&mut tx, None).await?;
+            nonce = tx.nonce().map(|nonce| nonce + 1);
             let signature = self.signer().sign_transaction(&tx).await?;
             bundle = bundle.push_transaction(tx.rlp_signed(&signature));
         }
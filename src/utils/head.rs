@@ -0,0 +1,327 @@
+use crate::utils::Backoff;
+use ethers::prelude::*;
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use futures::stream;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// No block for this long is regarded as a stalled subscription, a few times of the block interval.
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(60);
+
+// The latest block, the base of the simulations started before the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Head {
+    pub number: U64,
+    pub hash: H256,
+    pub timestamp: U256,
+    // None before London
+    pub base_fee: Option<U256>,
+    // The base fee of the block the bundle targets.
+    pub next_base_fee: Option<U256>,
+}
+
+impl Head {
+    // @return None for the pending block, it has no number or hash
+    pub fn from_block<T>(block: &Block<T>) -> Option<Self> {
+        Some(Self {
+            number: block.number?,
+            hash: block.hash?,
+            timestamp: block.timestamp,
+            base_fee: block.base_fee_per_gas,
+            next_base_fee: block.next_block_base_fee(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadEvent {
+    Connected,
+    NewHead(Head),
+    // The connect or subscribe fails, or the node closes the subscription.
+    Disconnected(String),
+    // No block within the heartbeat, the subscription is dropped.
+    Stalled(Duration),
+    Reconnecting { attempt: u32, delay: Duration },
+}
+
+impl fmt::Display for HeadEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected => write!(f, "Subscribed to new heads"),
+            Self::NewHead(head) => write!(
+                f,
+                "New head {} {:?}, base fee {}",
+                head.number,
+                head.hash,
+                head.base_fee.unwrap_or_default()
+            ),
+            Self::Disconnected(reason) => write!(f, "Disconnected: {reason}"),
+            Self::Stalled(heartbeat) => write!(f, "No block in {heartbeat:?}, resubscribe"),
+            Self::Reconnecting { attempt, delay } => {
+                write!(f, "Reconnect attempt {attempt} in {delay:?}")
+            }
+        }
+    }
+}
+
+type EventHandler = Box<dyn Fn(&HeadEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct HeadState {
+    head: Option<Head>,
+    // Woken by the next head.
+    waiter_list: Vec<oneshot::Sender<Head>>,
+}
+
+// Track the latest block by `eth_subscribe ["newHeads"]`, so the simulation started on a stale block can be dropped.
+// A reorg at the same height is a new head as well.
+pub struct HeadTracker {
+    wss_url: String,
+    backoff: Backoff,
+    heartbeat: Duration,
+    on_event: Option<EventHandler>,
+    state: Mutex<HeadState>,
+}
+
+impl HeadTracker {
+    // Connect lazily in `run`, the head is None until then.
+    pub fn init(wss_url: &str) -> Self {
+        Self {
+            wss_url: wss_url.into(),
+            backoff: Backoff::default(),
+            heartbeat: DEFAULT_HEARTBEAT,
+            on_event: None,
+            state: Mutex::new(HeadState::default()),
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // Set it longer than the block interval of the chain.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn with_event_handler(
+        mut self,
+        handler: impl Fn(&HeadEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_event = Some(Box::new(handler));
+        self
+    }
+
+    pub fn head(&self) -> Option<Head> {
+        self.state.lock().unwrap().head
+    }
+
+    // @return The first head other than `base`, None for any head
+    pub async fn next_head(&self, base: Option<&Head>) -> Head {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            match state.head {
+                Some(head) if Some(&head) != base => return head,
+                _ => {
+                    let (sender, receiver) = oneshot::channel();
+                    state.waiter_list.push(sender);
+                    receiver
+                }
+            }
+        };
+        match receiver.await {
+            Ok(head) => head,
+            // Never resolves once the tracker is dropped.
+            Err(_) => future::pending().await,
+        }
+    }
+
+    // Run on the current head, it's dropped and run again on the next head if a new block arrives first.
+    // @return The head of the output, None if it's still stale after `max_retry` runs again
+    pub async fn run_on_head<T, F, Fut>(&self, mut run: F, max_retry: usize) -> Option<(Head, T)>
+    where
+        F: FnMut(Head) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut head = self.next_head(None).await;
+        for _ in 0..=max_retry {
+            // The stale run is dropped with the select.
+            let base = head;
+            head = match future::select(Box::pin(run(base)), Box::pin(self.next_head(Some(&base))))
+                .await
+            {
+                Either::Left((output, _)) => return Some((base, output)),
+                Either::Right((next, _)) => next,
+            };
+        }
+
+        None
+    }
+
+    // Never returns, the head is kept during the outage.
+    pub async fn run(&self) {
+        let mut attempt = 0;
+        loop {
+            if attempt > 0 {
+                let delay = self.backoff.delay(attempt);
+                self.emit(HeadEvent::Reconnecting { attempt, delay });
+                tokio::time::sleep(delay).await;
+            }
+            attempt += 1;
+
+            let provider = match Provider::<Ws>::connect(self.wss_url.as_str()).await {
+                Ok(provider) => provider,
+                Err(error) => {
+                    self.emit(HeadEvent::Disconnected(error.to_string()));
+                    continue;
+                }
+            };
+            let subscription = match provider.subscribe_blocks().await {
+                Ok(subscription) => subscription,
+                Err(error) => {
+                    self.emit(HeadEvent::Disconnected(error.to_string()));
+                    continue;
+                }
+            };
+            self.emit(HeadEvent::Connected);
+            // The blocks during the outage are skipped, catch up without waiting for the next one.
+            if let Ok(Some(block)) = provider.get_block(BlockNumber::Latest).await {
+                self.update(&block);
+            }
+
+            let mut block_stream = stream::unfold(subscription, |mut subscription| async move {
+                match tokio::time::timeout(self.heartbeat, subscription.next()).await {
+                    Ok(Some(block)) => Some((block, subscription)),
+                    Ok(None) => {
+                        self.emit(HeadEvent::Disconnected("Subscription closed".into()));
+                        None
+                    }
+                    Err(_) => {
+                        self.emit(HeadEvent::Stalled(self.heartbeat));
+                        None
+                    }
+                }
+            })
+            .boxed();
+            while let Some(block) = block_stream.next().await {
+                // The backoff restarts once the subscription works.
+                attempt = 1;
+                self.update(&block);
+            }
+        }
+    }
+
+    // The older block is ignored, e.g. the latest block fetched after the subscribed one.
+    // @return True if the head changes
+    fn update<T>(&self, block: &Block<T>) -> bool {
+        let head = match Head::from_block(block) {
+            Some(head) => head,
+            None => return false,
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.head {
+                Some(current) if current.number > head.number || current == head => return false,
+                _ => state.head = Some(head),
+            }
+            for waiter in state.waiter_list.drain(..) {
+                let _ = waiter.send(head);
+            }
+        }
+        self.emit(HeadEvent::NewHead(head));
+        true
+    }
+
+    fn emit(&self, event: HeadEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Head, HeadTracker};
+    use ethers::prelude::*;
+    use futures::future;
+    use std::time::Duration;
+
+    fn to_block(number: u64) -> Block<TxHash> {
+        Block {
+            number: Some(number.into()),
+            hash: Some(H256::random()),
+            timestamp: U256::from(number * 12),
+            base_fee_per_gas: Some(U256::exp10(10)),
+            gas_limit: U256::from(30_000_000),
+            gas_used: U256::from(15_000_000),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn track_new_head() {
+        let tracker = HeadTracker::init("ws://127.0.0.1:9");
+        assert_eq!(tracker.head(), None);
+        assert!(tracker.update(&to_block(10)));
+        let head = tracker.head().unwrap();
+        assert_eq!(head.number, U64::from(10));
+        assert_eq!(head.next_base_fee, Some(U256::exp10(10)));
+
+        // The older block and the pending block are ignored, the reorg at the same height isn't.
+        assert!(!tracker.update(&to_block(9)));
+        assert!(!tracker.update(&Block::<TxHash>::default()));
+        assert!(tracker.update(&to_block(10)));
+        assert_ne!(tracker.head(), Some(head));
+        assert_eq!(Head::from_block(&Block::<TxHash>::default()), None);
+    }
+
+    #[tokio::test]
+    async fn drop_stale_run() {
+        let tracker = HeadTracker::init("ws://127.0.0.1:9");
+        tracker.update(&to_block(10));
+
+        // The first run is still on block 10 when block 11 arrives, it runs again on block 11.
+        let mut run_list = Vec::new();
+        let (head, output) = tracker
+            .run_on_head(
+                |head| {
+                    run_list.push(head.number);
+                    let tracker = &tracker;
+                    async move {
+                        if head.number == U64::from(10) {
+                            tracker.update(&to_block(11));
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                        }
+                        head.number.as_u64() * 2
+                    }
+                },
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(head.number, U64::from(11));
+        assert_eq!(output, 22);
+        assert_eq!(run_list, vec![U64::from(10), U64::from(11)]);
+
+        // Always stale
+        let output = tracker
+            .run_on_head(
+                |head| {
+                    let tracker = &tracker;
+                    async move {
+                        tracker.update(&to_block(head.number.as_u64() + 1));
+                        future::pending::<()>().await
+                    }
+                },
+                2,
+            )
+            .await;
+        assert_eq!(output, None::<(Head, ())>);
+        assert_eq!(tracker.head().unwrap().number, U64::from(14));
+    }
+}
@@ -1,27 +1,560 @@
+use crate::utils::{QueueStats, TxQueue};
 use ethers::prelude::*;
+use futures::future::{self, join_all};
+use futures::stream;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+// A quiet mempool for this long is regarded as a stalled subscription, even if the socket is open.
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(30);
+// The hash only subscription fetches the tx bodies concurrently.
+const MAX_CONCURRENT_FETCH: usize = 64;
+// The lowest pending txs are dropped once so many txs wait for the handler.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+const DEFAULT_DEDUPE_TTL: Duration = Duration::from_secs(300);
+
+// The subscription of the pending txs, tried in order until the node accepts one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingTxSubscription {
+    // `eth_subscribe ["newPendingTransactions", true]`, the full tx objects of geth 1.11+.
+    Full,
+    // `eth_subscribe ["alchemy_pendingTransactions", {"hashesOnly": false}]`.
+    Alchemy,
+    // `eth_subscribe ["newPendingTransactions"]`, then fetch each tx by hash.
+    Hash,
+}
+
+impl PendingTxSubscription {
+    fn params(&self) -> serde_json::Value {
+        match self {
+            Self::Full => json!(["newPendingTransactions", true]),
+            Self::Alchemy => json!(["alchemy_pendingTransactions", { "hashesOnly": false }]),
+            Self::Hash => json!(["newPendingTransactions"]),
+        }
+    }
+}
+
+impl fmt::Display for PendingTxSubscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// Some nodes accept the full flag but still send the hashes, both are handled.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PendingTx {
+    Full(Box<Transaction>),
+    Hash(TxHash),
+}
+
+impl PendingTx {
+    fn hash(&self) -> TxHash {
+        match self {
+            Self::Full(tx) => tx.hash,
+            Self::Hash(tx_hash) => *tx_hash,
+        }
+    }
+}
+
+// The delay before the n-th reconnect is `initial * 2^(n - 1)`, capped at `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+// The connection state of the listener, e.g. to log the outage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenEvent {
+    Connected(PendingTxSubscription),
+    // The node rejects the subscription, the next one is tried.
+    Unsupported {
+        subscription: PendingTxSubscription,
+        reason: String,
+    },
+    // The connect or subscribe fails, or the node closes the subscription.
+    Disconnected(String),
+    // No pending tx within the heartbeat, the subscription is dropped.
+    Stalled(Duration),
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+}
+
+impl fmt::Display for ListenEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected(subscription) => {
+                write!(f, "Subscribed to pending txs by {subscription}")
+            }
+            Self::Unsupported {
+                subscription,
+                reason,
+            } => write!(f, "Subscription {subscription} isn't supported: {reason}"),
+            Self::Disconnected(reason) => write!(f, "Disconnected: {reason}"),
+            Self::Stalled(heartbeat) => write!(f, "No pending tx in {heartbeat:?}, resubscribe"),
+            Self::Reconnecting { attempt, delay } => {
+                write!(f, "Reconnect attempt {attempt} in {delay:?}")
+            }
+        }
+    }
+}
+
+// The source url and its event.
+type EventHandler = Box<dyn Fn(&str, &ListenEvent) + Send + Sync>;
+
+// The txs of each source, e.g. to pick the fastest nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceStats {
+    pub url: String,
+    // The txs seen first by the source.
+    pub first: u64,
+    // The txs seen after another source, with the total lag behind the first one.
+    pub duplicate: u64,
+    pub total_lag: Duration,
+}
+
+impl SourceStats {
+    pub fn mean_lag(&self) -> Option<Duration> {
+        u32::try_from(self.duplicate)
+            .ok()
+            .filter(|duplicate| *duplicate > 0)
+            .map(|duplicate| self.total_lag / duplicate)
+    }
+}
+
+// tx hash => (first source, first seen), expired after the ttl.
+struct DedupeCache {
+    ttl: Duration,
+    seen: HashMap<TxHash, (usize, Instant)>,
+    expiry: VecDeque<(Instant, TxHash)>,
+}
+
+impl DedupeCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            seen: HashMap::new(),
+            expiry: VecDeque::new(),
+        }
+    }
+
+    // Look up without recording, e.g. before fetching the tx body by hash.
+    // @return The first source and the lag behind it, None if the tx isn't seen yet
+    fn seen(&mut self, tx_hash: TxHash, now: Instant) -> Option<(usize, Duration)> {
+        while let Some((seen_at, expired)) = self.expiry.front() {
+            if now.saturating_duration_since(*seen_at) < self.ttl {
+                break;
+            }
+            self.seen.remove(expired);
+            self.expiry.pop_front();
+        }
+
+        self.seen
+            .get(&tx_hash)
+            .map(|(first, seen_at)| (*first, now.saturating_duration_since(*seen_at)))
+    }
+
+    // @return None if the tx is seen first, otherwise the first source and the lag behind it
+    fn observe(
+        &mut self,
+        tx_hash: TxHash,
+        source: usize,
+        now: Instant,
+    ) -> Option<(usize, Duration)> {
+        let seen = self.seen(tx_hash, now);
+        if seen.is_none() {
+            self.seen.insert(tx_hash, (source, now));
+            self.expiry.push_back((now, tx_hash));
+        }
+        seen
+    }
+}
+
+struct Mempool {
+    cache: DedupeCache,
+    stats: Vec<SourceStats>,
+}
+
+// Merge the pending txs of the websocket nodes, each tx is handled once by the first source seeing it.
+// Each source reconnects with backoff on its own once the subscription ends or stalls, the others keep streaming.
 pub struct ListenPool {
-    wss_provider: Provider<Ws>,
+    source_list: Vec<String>,
     max_concurrent: Option<usize>,
+    backoff: Backoff,
+    heartbeat: Duration,
+    subscription_list: Vec<PendingTxSubscription>,
+    on_event: Option<EventHandler>,
+    mempool: Mutex<Mempool>,
+    queue: TxQueue,
 }
 
 impl ListenPool {
-    pub async fn init(wss_url: &str, max_concurrent: Option<usize>) -> Self {
+    // Connect lazily in `run`, the node may be down at the start as well.
+    pub fn init(wss_url: &str, max_concurrent: Option<usize>) -> Self {
         Self {
-            wss_provider: Provider::<Ws>::connect(wss_url)
-                .await
-                .expect("Websocket connect error"),
+            source_list: vec![wss_url.into()],
             max_concurrent,
+            backoff: Backoff::default(),
+            heartbeat: DEFAULT_HEARTBEAT,
+            subscription_list: vec![
+                PendingTxSubscription::Full,
+                PendingTxSubscription::Alchemy,
+                PendingTxSubscription::Hash,
+            ],
+            on_event: None,
+            mempool: Mutex::new(Mempool {
+                cache: DedupeCache::new(DEFAULT_DEDUPE_TTL),
+                stats: vec![SourceStats {
+                    url: wss_url.into(),
+                    ..Default::default()
+                }],
+            }),
+            queue: TxQueue::new(DEFAULT_QUEUE_CAPACITY),
         }
     }
 
-    pub async fn run<Fut: Future<Output = ()>, F: FnMut(TxHash) -> Fut>(&self, handle: F) {
-        self.wss_provider
-            .subscribe_pending_txs()
-            .await
-            .expect("Subscribe pending txs error")
-            .for_each_concurrent(self.max_concurrent, handle)
+    // Add another node to the merged stream.
+    pub fn with_source(mut self, wss_url: &str) -> Self {
+        self.source_list.push(wss_url.into());
+        self.mempool.get_mut().unwrap().stats.push(SourceStats {
+            url: wss_url.into(),
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // Set it longer than the interval between the pending txs, e.g. on a testnet.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    // The same hash within the ttl is a duplicate, set it longer than the tx stays pending on the slowest source.
+    pub fn with_dedupe_ttl(mut self, ttl: Duration) -> Self {
+        self.mempool.get_mut().unwrap().cache = DedupeCache::new(ttl);
+        self
+    }
+
+    // e.g. `[Hash]` to skip the full tx subscriptions.
+    pub fn with_subscription_list(mut self, subscription_list: Vec<PendingTxSubscription>) -> Self {
+        self.subscription_list = subscription_list;
+        self
+    }
+
+    pub fn with_event_handler(
+        mut self,
+        handler: impl Fn(&str, &ListenEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_event = Some(Box::new(handler));
+        self
+    }
+
+    // The txs wait in the queue for the handler, ordered by the score.
+    pub fn with_queue(mut self, queue: TxQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    // In the order of the sources.
+    pub fn source_stats(&self) -> Vec<SourceStats> {
+        self.mempool.lock().unwrap().stats.clone()
+    }
+
+    // Never returns, the txs pending during the outage of all sources are missed.
+    // The tx gone before it's fetched by hash is skipped, the handler takes the highest tx in the queue once it's free.
+    pub async fn run<Fut: Future<Output = ()>, F: FnMut(Transaction) -> Fut>(&self, handle: F) {
+        let source_list = join_all((0..self.source_list.len()).map(|source| self.listen(source)));
+        let queue = stream::unfold(&self.queue, |queue| async move {
+            Some((queue.pop().await, queue))
+        });
+        future::join(
+            source_list,
+            queue.for_each_concurrent(self.max_concurrent, handle),
+        )
+        .await;
+    }
+
+    async fn listen(&self, source: usize) {
+        let mut attempt = 0;
+        loop {
+            if attempt > 0 {
+                let delay = self.backoff.delay(attempt);
+                self.emit(source, ListenEvent::Reconnecting { attempt, delay });
+                tokio::time::sleep(delay).await;
+            }
+            attempt += 1;
+
+            let provider = match Provider::<Ws>::connect(self.source_list[source].as_str()).await {
+                Ok(provider) => provider,
+                Err(error) => {
+                    self.emit(source, ListenEvent::Disconnected(error.to_string()));
+                    continue;
+                }
+            };
+            let subscription = match self.subscribe(source, &provider).await {
+                Some(subscription) => subscription,
+                None => {
+                    self.emit(
+                        source,
+                        ListenEvent::Disconnected("No pending tx subscription is supported".into()),
+                    );
+                    continue;
+                }
+            };
+
+            let provider = &provider;
+            stream::unfold(subscription, |mut subscription| async move {
+                match tokio::time::timeout(self.heartbeat, subscription.next()).await {
+                    Ok(Some(pending_tx)) => Some((pending_tx, subscription)),
+                    Ok(None) => {
+                        self.emit(
+                            source,
+                            ListenEvent::Disconnected("Subscription closed".into()),
+                        );
+                        None
+                    }
+                    Err(_) => {
+                        self.emit(source, ListenEvent::Stalled(self.heartbeat));
+                        None
+                    }
+                }
+            })
+            // The backoff restarts once the subscription works.
+            .inspect(|_| attempt = 1)
+            // The tx delivered by another source isn't fetched again.
+            .filter(|pending_tx| future::ready(!self.is_duplicate(source, pending_tx.hash())))
+            .map(|pending_tx| async move {
+                match pending_tx {
+                    PendingTx::Full(tx) => Some(*tx),
+                    PendingTx::Hash(tx_hash) => {
+                        provider.get_transaction(tx_hash).await.ok().flatten()
+                    }
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_FETCH)
+            .filter_map(future::ready)
+            // Recorded only with the body, the tx failed to fetch may still come from another source.
+            .filter(|tx| future::ready(self.observe(source, tx.hash)))
+            .for_each(|tx| {
+                self.queue.push(tx);
+                future::ready(())
+            })
             .await;
+        }
+    }
+
+    // @return True if another source has delivered the tx, counted as a duplicate
+    fn is_duplicate(&self, source: usize, tx_hash: TxHash) -> bool {
+        let mut mempool = self.mempool.lock().unwrap();
+        match mempool.cache.seen(tx_hash, Instant::now()) {
+            Some((_, lag)) => {
+                let stats = &mut mempool.stats[source];
+                stats.duplicate += 1;
+                stats.total_lag += lag;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The fetch of the same tx by two sources may race, the later one is a duplicate.
+    // @return True if the tx is seen first
+    fn observe(&self, source: usize, tx_hash: TxHash) -> bool {
+        let mut mempool = self.mempool.lock().unwrap();
+        match mempool.cache.observe(tx_hash, source, Instant::now()) {
+            None => {
+                mempool.stats[source].first += 1;
+                true
+            }
+            Some((_, lag)) => {
+                let stats = &mut mempool.stats[source];
+                stats.duplicate += 1;
+                stats.total_lag += lag;
+                false
+            }
+        }
+    }
+
+    async fn subscribe<'p>(
+        &self,
+        source: usize,
+        provider: &'p Provider<Ws>,
+    ) -> Option<SubscriptionStream<'p, Ws, PendingTx>> {
+        for subscription in &self.subscription_list {
+            match provider.subscribe(subscription.params()).await {
+                Ok(stream) => {
+                    self.emit(source, ListenEvent::Connected(*subscription));
+                    return Some(stream);
+                }
+                Err(error) => self.emit(
+                    source,
+                    ListenEvent::Unsupported {
+                        subscription: *subscription,
+                        reason: error.to_string(),
+                    },
+                ),
+            }
+        }
+
+        None
+    }
+
+    fn emit(&self, source: usize, event: ListenEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(&self.source_list[source], &event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, DedupeCache, ListenEvent, ListenPool, PendingTx};
+    use ethers::prelude::*;
+    use futures::{
+        channel::mpsc,
+        future::{self, Either},
+        StreamExt,
+    };
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(3), Duration::from_millis(400));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn decode_pending_tx() {
+        let tx_hash = TxHash::random();
+        let pending_tx: PendingTx = serde_json::from_value(serde_json::json!(tx_hash)).unwrap();
+        assert!(matches!(pending_tx, PendingTx::Hash(hash) if hash == tx_hash));
+
+        let tx = Transaction {
+            hash: tx_hash,
+            from: Address::random(),
+            ..Default::default()
+        };
+        let pending_tx: PendingTx = serde_json::from_value(serde_json::json!(tx)).unwrap();
+        assert!(matches!(pending_tx, PendingTx::Full(full) if *full == tx));
+    }
+
+    #[tokio::test]
+    async fn dedupe_pending_tx() {
+        let mut cache = DedupeCache::new(Duration::from_secs(10));
+        let tx_hash = TxHash::random();
+        let now = Instant::now();
+        assert_eq!(cache.observe(tx_hash, 1, now), None);
+        assert_eq!(
+            cache.observe(tx_hash, 0, now + Duration::from_millis(30)),
+            Some((1, Duration::from_millis(30)))
+        );
+        assert_eq!(
+            cache.observe(TxHash::random(), 0, now + Duration::from_secs(5)),
+            None
+        );
+
+        // The lookup before fetching the body doesn't record it.
+        let fetched = TxHash::random();
+        assert_eq!(cache.seen(fetched, now), None);
+        assert_eq!(
+            cache.observe(fetched, 2, now + Duration::from_secs(1)),
+            None
+        );
+
+        // Seen first again after the ttl.
+        assert_eq!(
+            cache.observe(tx_hash, 0, now + Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(cache.seen.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reconnect_unreachable_node() {
+        let (sender, mut receiver) = mpsc::unbounded();
+        // Nothing listens on the discard port.
+        let listen_pool = ListenPool::init("ws://127.0.0.1:9", None)
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(20),
+            })
+            .with_event_handler(move |_, event| {
+                let _ = sender.unbounded_send(event.clone());
+            });
+        // Collect the events until the third reconnect, the timeout only guards against a hang.
+        let event_list = async {
+            let mut event_list = Vec::new();
+            while let Some(event) = receiver.next().await {
+                let done = matches!(event, ListenEvent::Reconnecting { attempt: 3, .. });
+                event_list.push(event);
+                if done {
+                    break;
+                }
+            }
+            event_list
+        };
+        let run = listen_pool.run(|_| async { unreachable!() });
+        let event_list = match tokio::time::timeout(
+            Duration::from_secs(30),
+            future::select(Box::pin(run), Box::pin(event_list)),
+        )
+        .await
+        .unwrap()
+        {
+            Either::Left(_) => unreachable!(),
+            Either::Right((event_list, _)) => event_list,
+        };
+
+        assert!(matches!(event_list[0], ListenEvent::Disconnected(_)));
+        assert_eq!(
+            event_list[1],
+            ListenEvent::Reconnecting {
+                attempt: 1,
+                delay: Duration::from_millis(10)
+            }
+        );
+        assert!(event_list.contains(&ListenEvent::Reconnecting {
+            attempt: 3,
+            delay: Duration::from_millis(20)
+        }));
     }
 }
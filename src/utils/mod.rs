@@ -1,11 +1,35 @@
+mod backrun;
 mod base;
+mod bribe;
+mod bundle_report;
+mod config;
 mod contract;
+mod error;
 mod flashbot;
+mod head;
 mod listen;
+mod price;
+mod queue;
+mod relay;
+mod relay_signer;
 mod simulate;
 
+// The abigen call structs of the router and WETH clash with `contract`.
+pub use backrun::{
+    best_amount_in, decode_swap, find_route_list, simulate_route, Backrun, BackrunOpportunity, Hop,
+    PendingSwap,
+};
 pub use base::*;
+pub use bribe::*;
+pub use bundle_report::*;
+pub use config::*;
 pub use contract::*;
+pub use error::*;
 pub use flashbot::*;
+pub use head::*;
 pub use listen::*;
+pub use price::*;
+pub use queue::*;
+pub use relay::*;
+pub use relay_signer::*;
 pub use simulate::*;
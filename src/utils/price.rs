@@ -0,0 +1,269 @@
+use crate::utils::{call_contract, wrapped_native, ArbitrageError, ERC20Token};
+use cfmms::pool::{Pool, UniswapV2Pool, UniswapV3Pool};
+use ethers::prelude::*;
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+abigen!(
+    IUniswapV2Factory,
+    r#"[
+        function getPair(address tokenA, address tokenB) external view returns (address pair)
+    ]"#;
+
+    IUniswapV3Factory,
+    r#"[
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool)
+    ]"#;
+);
+
+const V3_FEE_LIST: [u32; 5] = [100, 500, 2500, 3000, 10000];
+
+#[derive(Default)]
+struct PriceCache {
+    block: U64,
+    // token => the pool with the most native token liquidity
+    pools: HashMap<Address, Option<Pool>>,
+}
+
+// Price the token in native token with the on-chain reserves of uniswap v2 / v3 style pools.
+// The pool is cached per simulated block.
+pub struct PriceOracle<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+    wrapped_native: Option<Address>,
+    v2_factory_list: Vec<Address>,
+    v3_factory_list: Vec<Address>,
+    cache: Mutex<PriceCache>,
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> PriceOracle<M, S> {
+    pub async fn init(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError> {
+        let chain_id = client.get_chainid().await?.as_u64();
+        let (v2_factory_list, v3_factory_list) = default_factory_list(chain_id);
+
+        Ok(Self {
+            client,
+            wrapped_native: wrapped_native(chain_id),
+            v2_factory_list,
+            v3_factory_list,
+            cache: Mutex::new(PriceCache::default()),
+        })
+    }
+
+    pub fn with_factory_list(mut self, v2: Vec<Address>, v3: Vec<Address>) -> Self {
+        self.v2_factory_list = v2;
+        self.v3_factory_list = v3;
+        self
+    }
+
+    // @param block The block to price the token at, e.g. the simulated one
+    // @return The value of `amount` token in wei, none if there is no pool to price it.
+    pub async fn to_native(
+        &self,
+        token: Address,
+        amount: U256,
+        block: U64,
+    ) -> Result<Option<U256>, ArbitrageError> {
+        let wrapped_native = match self.wrapped_native {
+            Some(wrapped_native) => wrapped_native,
+            None => return Ok(None),
+        };
+        if token == wrapped_native {
+            return Ok(Some(amount));
+        }
+        if amount.is_zero() {
+            return Ok(Some(amount));
+        }
+
+        let pool = self.get_pool(token, wrapped_native, block).await?;
+        match pool {
+            // Swap out amount rather than spot price, the slippage is also counted.
+            Some(pool) => Ok(Some(
+                pool.simulate_swap(token, amount, self.client.clone())
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // The pool pricing the token in `to_native`, the one with the most native token liquidity.
+    // @return None if there is no pool to price it
+    pub async fn native_pool(
+        &self,
+        token: Address,
+        block: U64,
+    ) -> Result<Option<Pool>, ArbitrageError> {
+        match self.wrapped_native {
+            Some(wrapped_native) => self.get_pool(token, wrapped_native, block).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn get_pool(
+        &self,
+        token: Address,
+        wrapped_native: Address,
+        block: U64,
+    ) -> Result<Option<Pool>, ArbitrageError> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            // Only the newest block is cached, the lookup of an older one bypasses the cache.
+            if cache.block < block {
+                cache.block = block;
+                cache.pools.clear();
+            }
+            if cache.block == block {
+                if let Some(pool) = cache.pools.get(&token) {
+                    return Ok(*pool);
+                }
+            }
+        }
+
+        let pool = self.find_pool(token, wrapped_native, block).await?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.block == block {
+            cache.pools.insert(token, pool);
+        }
+        Ok(pool)
+    }
+
+    async fn find_pool(
+        &self,
+        token: Address,
+        wrapped_native: Address,
+        block: U64,
+    ) -> Result<Option<Pool>, ArbitrageError> {
+        let client = self.client.clone();
+
+        let v2_pair_list = join_all(self.v2_factory_list.iter().map(|factory| {
+            let factory = IUniswapV2Factory::new(*factory, client.clone());
+            async move {
+                call_contract(
+                    &self.client,
+                    factory.get_pair(token, wrapped_native).block(block),
+                )
+                .await
+                .ok()
+            }
+        }))
+        .await;
+        let v3_pool_list = join_all(self.v3_factory_list.iter().flat_map(|factory| {
+            let factory = IUniswapV3Factory::new(*factory, client.clone());
+            V3_FEE_LIST.map(|fee| {
+                let call = factory.get_pool(token, wrapped_native, fee).block(block);
+                async move { call_contract(&self.client, call).await.ok() }
+            })
+        }))
+        .await;
+
+        // The pool with the most native token is the most accurate one.
+        let wrapped_native_token = ERC20Token::new(wrapped_native, client.clone());
+        let mut best: Option<(bool, Address, U256)> = None;
+        for (is_v2, address) in v2_pair_list
+            .into_iter()
+            .map(|address| (true, address))
+            .chain(v3_pool_list.into_iter().map(|address| (false, address)))
+        {
+            // Not all factories are deployed on the chain, just skip the failed one.
+            let address = match address {
+                Some(address) if !address.is_zero() => address,
+                _ => continue,
+            };
+            // The pool whose balance can't be read isn't comparable, the other pools still price the token.
+            let liquidity = match call_contract(
+                &self.client,
+                wrapped_native_token.balance_of(address).block(block),
+            )
+            .await
+            {
+                Ok(liquidity) => liquidity,
+                Err(_) => continue,
+            };
+            if !matches!(best, Some((.., best_liquidity)) if best_liquidity >= liquidity) {
+                best = Some((is_v2, address, liquidity));
+            }
+        }
+
+        Ok(match best {
+            Some((true, address, _)) => Some(Pool::UniswapV2(
+                UniswapV2Pool::new_from_address(address, client).await?,
+            )),
+            Some((false, address, _)) => Some(Pool::UniswapV3(
+                UniswapV3Pool::new_from_address(address, client).await?,
+            )),
+            None => None,
+        })
+    }
+}
+
+// @return (uniswap v2 style factory list, uniswap v3 style factory list)
+pub fn default_factory_list(chain_id: u64) -> (Vec<Address>, Vec<Address>) {
+    let (v2, v3): (&[&str], &[&str]) = match chain_id {
+        1 => (
+            &[
+                // uniswap v2
+                "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f",
+                // sushiswap
+                "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac",
+            ],
+            // uniswap v3
+            &["0x1F98431c8aD98523631AE4a59f267346ea31F984"],
+        ),
+        5 => (
+            &["0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"],
+            &["0x1F98431c8aD98523631AE4a59f267346ea31F984"],
+        ),
+        56 => (
+            // pancakeswap v2
+            &["0xcA143Ce32Fe78f1f7019d7d551a6402fC5350c73"],
+            // pancakeswap v3
+            &["0x0BFbCF9fa4f9C56B0F40a671Ad40E0805A091865"],
+        ),
+        137 => (
+            &[
+                // quickswap
+                "0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32",
+                // sushiswap
+                "0xc35DADB65012eC5796536bD9864eD8773aBc74C4",
+            ],
+            &["0x1F98431c8aD98523631AE4a59f267346ea31F984"],
+        ),
+        _ => (&[], &[]),
+    };
+
+    (
+        v2.iter().map(|address| address.parse().unwrap()).collect(),
+        v3.iter().map(|address| address.parse().unwrap()).collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriceOracle;
+    use ethers::{core::rand::thread_rng, prelude::*};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn skip_pool_without_balance() {
+        let (provider, mock) = Provider::mocked();
+        let client = Arc::new(SignerMiddleware::new(
+            provider,
+            LocalWallet::new(&mut thread_rng()),
+        ));
+        mock.push::<U256, _>(U256::one()).unwrap();
+        let oracle = PriceOracle::init(client)
+            .await
+            .unwrap()
+            .with_factory_list(vec![Address::random()], Vec::new());
+        let (token, block) = (Address::random(), U64::from(100));
+
+        // `getPair` finds the pair, but `balanceOf` of it fails without a response.
+        let pair: Bytes = abi::encode(&[abi::Token::Address(Address::random())]).into();
+        mock.push::<Bytes, _>(pair).unwrap();
+        assert!(oracle.native_pool(token, block).await.unwrap().is_none());
+
+        // The pool of the simulated block is cached, no more request for it.
+        assert!(oracle.native_pool(token, block).await.unwrap().is_none());
+        assert!(oracle.native_pool(token, block + 1).await.is_ok());
+    }
+}
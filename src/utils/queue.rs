@@ -0,0 +1,249 @@
+use ethers::prelude::*;
+use futures::channel::oneshot;
+use futures::future;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+
+// Order the pending txs of `TxQueue`, see `AddressScore` to combine with the addresses.
+pub trait TxScore: Debug + Send + Sync {
+    // The higher the earlier, the lowest one is dropped first once the queue is full.
+    fn score(&self, tx: &Transaction) -> U256;
+}
+
+// The max fee of the EIP-1559 tx or the legacy gas price, the default score.
+#[derive(Debug, Clone, Default)]
+pub struct GasPriceScore;
+
+impl TxScore for GasPriceScore {
+    fn score(&self, tx: &Transaction) -> U256 {
+        tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default()
+    }
+}
+
+// The native token sent, e.g. the swap from ETH.
+#[derive(Debug, Clone, Default)]
+pub struct ValueScore;
+
+impl TxScore for ValueScore {
+    fn score(&self, tx: &Transaction) -> U256 {
+        tx.value
+    }
+}
+
+// Weight the inner score by the target contract and the sender in percent, 100 if not set.
+// e.g. 200 for the router, 0 for the sender whose txs are never profitable.
+#[derive(Debug)]
+pub struct AddressScore {
+    inner: Box<dyn TxScore>,
+    target_map: HashMap<Address, u64>,
+    sender_map: HashMap<Address, u64>,
+}
+
+impl AddressScore {
+    pub fn new(inner: impl TxScore + 'static) -> Self {
+        Self {
+            inner: Box::new(inner),
+            target_map: HashMap::new(),
+            sender_map: HashMap::new(),
+        }
+    }
+
+    pub fn with_target(mut self, target: Address, percent: u64) -> Self {
+        self.target_map.insert(target, percent);
+        self
+    }
+
+    pub fn with_sender(mut self, sender: Address, percent: u64) -> Self {
+        self.sender_map.insert(sender, percent);
+        self
+    }
+}
+
+impl TxScore for AddressScore {
+    fn score(&self, tx: &Transaction) -> U256 {
+        let target = tx
+            .to
+            .and_then(|to| self.target_map.get(&to))
+            .copied()
+            .unwrap_or(100);
+        let sender = self.sender_map.get(&tx.from).copied().unwrap_or(100);
+        self.inner
+            .score(tx)
+            .saturating_mul(U256::from(target) * U256::from(sender))
+            / 10_000
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub len: usize,
+    pub pushed: u64,
+    // Shed by the higher txs once the queue is full.
+    pub dropped: u64,
+}
+
+impl fmt::Display for QueueStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} queued, {} pushed, {} dropped",
+            self.len, self.pushed, self.dropped
+        )
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    // (score, reversed arrival order) => tx, the last one is popped first and the first one is dropped first.
+    // The earlier tx wins the same score.
+    tx_map: BTreeMap<(U256, Reverse<u64>), Transaction>,
+    sequence: u64,
+    // The idle consumers, only if the queue is empty.
+    waiter_list: VecDeque<oneshot::Sender<Transaction>>,
+    pushed: u64,
+    dropped: u64,
+}
+
+// The bounded priority queue between the listener and the simulations, the push never waits for the simulations.
+pub struct TxQueue {
+    capacity: usize,
+    score: Box<dyn TxScore>,
+    state: Mutex<QueueState>,
+}
+
+impl TxQueue {
+    // Ordered by `GasPriceScore`, the capacity is at least 1.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            score: Box::new(GasPriceScore),
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    pub fn with_score(mut self, score: impl TxScore + 'static) -> Self {
+        self.score = Box::new(score);
+        self
+    }
+
+    // @return The dropped tx if the queue is full, the new one itself if it's the lowest
+    pub fn push(&self, tx: Transaction) -> Option<Transaction> {
+        let mut state = self.state.lock().unwrap();
+        state.pushed += 1;
+
+        // Hand over to the idle consumer directly, the one gone returns the tx.
+        let mut tx = tx;
+        while let Some(waiter) = state.waiter_list.pop_front() {
+            match waiter.send(tx) {
+                Ok(()) => return None,
+                Err(back) => tx = back,
+            }
+        }
+
+        let key = (self.score.score(&tx), Reverse(state.sequence));
+        state.sequence += 1;
+        state.tx_map.insert(key, tx);
+        if state.tx_map.len() > self.capacity {
+            state.dropped += 1;
+            return state.tx_map.pop_first().map(|(_, tx)| tx);
+        }
+
+        None
+    }
+
+    // Wait for the highest tx, the tx handed over is lost if the future is dropped right after.
+    pub async fn pop(&self) -> Transaction {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if let Some((_, tx)) = state.tx_map.pop_last() {
+                return tx;
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiter_list.push_back(sender);
+            receiver
+        };
+        match receiver.await {
+            Ok(tx) => tx,
+            // Never resolves once the queue is dropped.
+            Err(_) => future::pending().await,
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            len: state.tx_map.len(),
+            pushed: state.pushed,
+            dropped: state.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressScore, GasPriceScore, QueueStats, TxQueue, TxScore, ValueScore};
+    use ethers::prelude::*;
+    use futures::future;
+
+    fn to_tx(gas_price: u64) -> Transaction {
+        Transaction {
+            hash: TxHash::random(),
+            from: Address::random(),
+            to: Some(Address::random()),
+            gas_price: Some(gas_price.into()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn shed_lowest_tx() {
+        let queue = TxQueue::new(2);
+        let (low, mid, high, same) = (to_tx(1), to_tx(5), to_tx(9), to_tx(5));
+        assert_eq!(queue.push(mid.clone()), None);
+        assert_eq!(queue.push(low.clone()), None);
+        assert_eq!(queue.push(high.clone()), Some(low));
+        // The earlier one wins the same score.
+        assert_eq!(queue.push(same.clone()), Some(same));
+        assert_eq!(
+            queue.stats(),
+            QueueStats {
+                len: 2,
+                pushed: 4,
+                dropped: 2
+            }
+        );
+
+        assert_eq!(queue.pop().await, high);
+        assert_eq!(queue.pop().await, mid);
+        assert_eq!(queue.stats().len, 0);
+    }
+
+    #[tokio::test]
+    async fn hand_over_to_idle_consumer() {
+        let queue = TxQueue::new(1);
+        let tx = to_tx(1);
+        // The pop waits first, the push hands the tx over without queueing it.
+        let (popped, dropped) = future::join(queue.pop(), async { queue.push(tx.clone()) }).await;
+        assert_eq!(popped, tx);
+        assert_eq!(dropped, None);
+        assert_eq!(queue.stats().len, 0);
+    }
+
+    #[tokio::test]
+    async fn weight_by_address() {
+        let mut tx = to_tx(10);
+        tx.value = U256::from(300);
+        assert_eq!(GasPriceScore.score(&tx), U256::from(10));
+        assert_eq!(ValueScore.score(&tx), U256::from(300));
+
+        let score = AddressScore::new(ValueScore)
+            .with_target(tx.to.unwrap(), 200)
+            .with_sender(tx.from, 50);
+        assert_eq!(score.score(&tx), U256::from(300));
+        let score = score.with_sender(tx.from, 0);
+        assert_eq!(score.score(&tx), U256::zero());
+        assert_eq!(AddressScore::new(GasPriceScore).score(&tx), U256::from(10));
+    }
+}
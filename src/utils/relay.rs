@@ -0,0 +1,237 @@
+use crate::utils::{ArbitrageError, ErrorSource, RelaySigner};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::utils::hex;
+use ethers_flashbots::{BundleHash, BundleRequest, BundleTransaction, Relay, SimulatedBundle};
+use futures::future::join_all;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+// bloXroute needs an auth header, so it isn't in the default relay list.
+pub const BLOXROUTE_URL: &str = "https://mev.api.blxrbdn.com";
+
+// Some builders accept the bundle without returning the bundle hash.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendBundleResponse {
+    bundle_hash: Option<BundleHash>,
+}
+
+#[async_trait]
+pub trait BundleRelay: Send + Sync {
+    fn url(&self) -> &Url;
+
+    // @return The bundle hash, none if the relay doesn't return it
+    async fn send_bundle(&self, bundle: &BundleRequest) -> Result<Option<BundleHash>, ErrorSource>;
+}
+
+// `eth_sendBundle` signed with the `X-Flashbots-Signature` header.
+// Flashbots, beaverbuild, Titan and most builders follow this API.
+pub struct FlashbotsRelay {
+    url: Url,
+    signer: RelaySigner,
+    // Rebuilt once the signer is rotated.
+    inner: Mutex<Option<(Address, Arc<Relay<LocalWallet>>)>>,
+}
+
+impl FlashbotsRelay {
+    pub fn new(url: Url, signer: RelaySigner) -> Self {
+        Self {
+            url,
+            signer,
+            inner: Mutex::new(None),
+        }
+    }
+
+    // `eth_callBundle`, simulate the bundle on top of the simulation block.
+    pub async fn simulate_bundle(
+        &self,
+        bundle: &BundleRequest,
+    ) -> Result<SimulatedBundle, ErrorSource> {
+        Ok(self.relay().request("eth_callBundle", [bundle]).await?)
+    }
+
+    fn relay(&self) -> Arc<Relay<LocalWallet>> {
+        let wallet = self.signer.wallet();
+        let mut inner = self.inner.lock().unwrap();
+        match &*inner {
+            Some((address, relay)) if *address == wallet.address() => relay.clone(),
+            _ => {
+                let relay = Arc::new(Relay::new(self.url.clone(), Some(wallet.clone())));
+                *inner = Some((wallet.address(), relay.clone()));
+                relay
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BundleRelay for FlashbotsRelay {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    async fn send_bundle(&self, bundle: &BundleRequest) -> Result<Option<BundleHash>, ErrorSource> {
+        let response: Option<SendBundleResponse> =
+            self.relay().request("eth_sendBundle", [bundle]).await?;
+        Ok(response.and_then(|response| response.bundle_hash))
+    }
+}
+
+// `blxr_submit_bundle` authorized by the account auth header.
+pub struct BloxrouteRelay {
+    url: Url,
+    inner: Http,
+}
+
+impl BloxrouteRelay {
+    pub fn new(url: Url, auth: &str) -> Result<Self, ArbitrageError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(auth).map_err(|error| ArbitrageError::Relay(Box::new(error)))?,
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|error| ArbitrageError::Relay(Box::new(error)))?;
+
+        Ok(Self {
+            inner: Http::new_with_client(url.clone(), client),
+            url,
+        })
+    }
+}
+
+#[async_trait]
+impl BundleRelay for BloxrouteRelay {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    async fn send_bundle(&self, bundle: &BundleRequest) -> Result<Option<BundleHash>, ErrorSource> {
+        let block = bundle.block().ok_or("Missing the target block of bundle")?;
+        let tx_list = bundle
+            .transactions()
+            .iter()
+            .map(|tx| match tx {
+                BundleTransaction::Signed(tx) => hex::encode(tx.rlp()),
+                BundleTransaction::Raw(tx) => hex::encode(tx),
+            })
+            .collect::<Vec<_>>();
+
+        let response: SendBundleResponse = JsonRpcClient::request(
+            &self.inner,
+            "blxr_submit_bundle",
+            json!({
+                "transaction": tx_list,
+                "block_number": format!("{block:#x}"),
+            }),
+        )
+        .await?;
+        Ok(response.bundle_hash)
+    }
+}
+
+#[derive(Debug)]
+pub struct RelayResult {
+    pub relay: Url,
+    pub result: Result<Option<BundleHash>, ErrorSource>,
+}
+
+impl fmt::Display for RelayResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.result {
+            Ok(Some(bundle_hash)) => write!(f, "{}: {bundle_hash:?}", self.relay),
+            Ok(None) => write!(f, "{}: accepted", self.relay),
+            Err(error) => write!(f, "{}: {error}", self.relay),
+        }
+    }
+}
+
+// Send the same signed bundle to all relays at once, one relay failing doesn't affect the others.
+#[derive(Default)]
+pub struct RelayList {
+    relay_list: Vec<Box<dyn BundleRelay>>,
+}
+
+impl RelayList {
+    pub fn push(&mut self, relay: Box<dyn BundleRelay>) {
+        self.relay_list.push(relay);
+    }
+
+    pub fn url_list(&self) -> Vec<&Url> {
+        self.relay_list.iter().map(|relay| relay.url()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relay_list.is_empty()
+    }
+
+    // @return The result of each relay in order
+    pub async fn send_bundle(&self, bundle: &BundleRequest) -> Vec<RelayResult> {
+        join_all(self.relay_list.iter().map(|relay| async move {
+            RelayResult {
+                relay: relay.url().clone(),
+                result: relay.send_bundle(bundle).await,
+            }
+        }))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BundleRelay, ErrorSource, RelayList};
+    use async_trait::async_trait;
+    use ethers_flashbots::{BundleHash, BundleRequest};
+    use url::Url;
+
+    struct MockRelay {
+        url: Url,
+        accept: bool,
+    }
+
+    #[async_trait]
+    impl BundleRelay for MockRelay {
+        fn url(&self) -> &Url {
+            &self.url
+        }
+
+        async fn send_bundle(
+            &self,
+            _bundle: &BundleRequest,
+        ) -> Result<Option<BundleHash>, ErrorSource> {
+            match self.accept {
+                true => Ok(Some(BundleHash::zero())),
+                false => Err("bundle rejected".into()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_result_of_each_relay() {
+        let mut relay_list = RelayList::default();
+        for (url, accept) in [("https://a.xyz", false), ("https://b.xyz", true)] {
+            relay_list.push(Box::new(MockRelay {
+                url: url.parse().unwrap(),
+                accept,
+            }));
+        }
+
+        let result_list = relay_list.send_bundle(&BundleRequest::new()).await;
+        assert_eq!(result_list.len(), 2);
+        assert_eq!(
+            result_list[0].to_string(),
+            "https://a.xyz/: bundle rejected"
+        );
+        assert_eq!(
+            result_list[1].result.as_ref().unwrap(),
+            &Some(BundleHash::zero())
+        );
+    }
+}
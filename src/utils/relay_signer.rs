@@ -0,0 +1,186 @@
+use crate::utils::ArbitrageError;
+use ethers::core::rand::thread_rng;
+use ethers::prelude::*;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+// Where the relay signing key comes from, it's kept apart from the tx signer.
+#[derive(Clone)]
+pub enum RelaySignerSource {
+    // A new key on every start, the relay sees a new searcher without reputation.
+    Random,
+    Key(LocalWallet),
+    // Reloaded when the file changes, replace the file to rotate the key.
+    Keystore { path: PathBuf, password: String },
+}
+
+// Never print the key or password.
+impl fmt::Debug for RelaySignerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Random => write!(f, "Random"),
+            Self::Key(wallet) => write!(f, "Key({:?})", wallet.address()),
+            Self::Keystore { path, .. } => write!(f, "Keystore({})", path.display()),
+        }
+    }
+}
+
+// The key change of the watched keystore, e.g. to log the rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelaySignerEvent {
+    Rotated(Address),
+    // The current key is kept, e.g. the file is being written.
+    ReloadFailed(String),
+}
+
+impl fmt::Display for RelaySignerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rotated(address) => write!(f, "Rotated to {address:?}"),
+            Self::ReloadFailed(error) => write!(f, "Reload error: {error}"),
+        }
+    }
+}
+
+// The identity signing the relay requests, the relay scores the reputation by its address.
+// All relays share it, and it can be rotated while running.
+#[derive(Clone, Debug)]
+pub struct RelaySigner {
+    inner: Arc<RwLock<LocalWallet>>,
+}
+
+impl RelaySigner {
+    pub fn new(wallet: LocalWallet) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(wallet)),
+        }
+    }
+
+    pub fn random() -> Self {
+        Self::new(LocalWallet::new(&mut thread_rng()))
+    }
+
+    pub fn load(source: &RelaySignerSource) -> Result<Self, ArbitrageError> {
+        Ok(match source {
+            RelaySignerSource::Random => Self::random(),
+            RelaySignerSource::Key(wallet) => Self::new(wallet.clone()),
+            RelaySignerSource::Keystore { path, password } => {
+                Self::new(LocalWallet::decrypt_keystore(path, password)?)
+            }
+        })
+    }
+
+    pub fn wallet(&self) -> LocalWallet {
+        self.inner.read().unwrap().clone()
+    }
+
+    pub fn address(&self) -> Address {
+        self.inner.read().unwrap().address()
+    }
+
+    // The next relay request is signed by the new key.
+    pub fn rotate(&self, wallet: LocalWallet) {
+        *self.inner.write().unwrap() = wallet;
+    }
+
+    // Check the keystore every `interval` and rotate the key once it's modified, `handler` receives each reload.
+    // Nothing to watch for the other sources.
+    pub fn watch(
+        &self,
+        source: &RelaySignerSource,
+        interval: Duration,
+        handler: impl Fn(&RelaySignerEvent) + Send + Sync + 'static,
+    ) {
+        let (path, password) = match source {
+            RelaySignerSource::Keystore { path, password } => (path.clone(), password.clone()),
+            _ => return,
+        };
+        let signer = self.clone();
+        tokio::spawn(async move {
+            let mut modified = modified_at(&path);
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let current = modified_at(&path);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+
+                // The key derivation of keystore is slow, don't block the listener.
+                let (path, password) = (path.clone(), password.clone());
+                let event = match tokio::task::spawn_blocking(move || {
+                    LocalWallet::decrypt_keystore(path, password)
+                })
+                .await
+                {
+                    Ok(Ok(wallet)) => {
+                        signer.rotate(wallet);
+                        RelaySignerEvent::Rotated(signer.address())
+                    }
+                    // Keep the current key, e.g. the file is being written.
+                    Ok(Err(error)) => RelaySignerEvent::ReloadFailed(error.to_string()),
+                    Err(error) => RelaySignerEvent::ReloadFailed(error.to_string()),
+                };
+                handler(&event);
+            }
+        });
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RelaySigner, RelaySignerEvent, RelaySignerSource};
+    use ethers::core::rand::thread_rng;
+    use ethers::prelude::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn rotate_keystore_while_running() {
+        let dir = std::env::temp_dir().join(format!("relay-signer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, name) =
+            LocalWallet::new_keystore(&dir, &mut thread_rng(), "password", None).unwrap();
+        let path = dir.join(name);
+        let source = RelaySignerSource::Keystore {
+            path: path.clone(),
+            password: "password".into(),
+        };
+
+        let signer = RelaySigner::load(&source).unwrap();
+        assert_eq!(signer.address(), first.address());
+        assert!(!format!("{source:?}").contains("password"));
+
+        let event_list = Arc::new(Mutex::new(Vec::new()));
+        signer.watch(&source, Duration::from_millis(10), {
+            let event_list = event_list.clone();
+            move |event| event_list.lock().unwrap().push(event.clone())
+        });
+        // The mtime resolution of some file systems is 1 second.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let (second, name) =
+            LocalWallet::new_keystore(&dir, &mut thread_rng(), "password", None).unwrap();
+        std::fs::rename(dir.join(name), &path).unwrap();
+
+        let rotated = RelaySignerEvent::Rotated(second.address());
+        for _ in 0..200 {
+            if event_list.lock().unwrap().contains(&rotated) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(signer.address(), second.address());
+        assert!(event_list.lock().unwrap().contains(&rotated));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
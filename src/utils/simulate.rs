@@ -1,13 +1,32 @@
+mod backend;
+mod call_tree;
 mod state;
 mod strategy;
+mod substitute;
+mod swap;
 
+use crate::utils::{
+    flash_lender_list, swap_router, wrapped_native, ArbitrageError, ConfigError, Head, PriceOracle,
+    SwapRouter,
+};
+pub use backend::{evm::EvmBackend, geth::GethBackend, parity::ParityBackend, SimulateBackend};
+pub use call_tree::{CallTree, CallTreeError};
 use ethers::prelude::*;
-use futures::future::join_all;
-use state::{base::AnalyzeState, eth::AnalyzeEth, token::AnalyzeToken};
-use std::collections::HashMap;
-use std::error::Error;
+use futures::future::try_join_all;
+use state::{
+    base::AnalyzeState,
+    eth::AnalyzeEth,
+    token::{self, AnalyzeToken},
+};
 use std::iter::Sum;
 use std::ops::Deref;
+use std::sync::Arc;
+use strategy::flashloan::Flashloan;
+pub use strategy::{
+    base::{Rejection, Strategy, StrategyList},
+    transfer::TransferStrategy,
+};
+pub use substitute::{AddressSubstitute, Encoding, Substitution};
 
 struct SumU256(U256);
 impl Sum for SumU256 {
@@ -16,152 +35,341 @@ impl Sum for SumU256 {
     }
 }
 
-pub type SimulateTrace = BlockTrace;
+// Trace of the simulated tx.
+// `logs` is empty when the node doesn't return them, e.g. parity `trace_call`.
+#[derive(Debug, Clone)]
+pub struct SimulateTrace {
+    pub inner: BlockTrace,
+    pub logs: Vec<Log>,
+}
+
+impl Deref for SimulateTrace {
+    type Target = BlockTrace;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl From<BlockTrace> for SimulateTrace {
+    fn from(inner: BlockTrace) -> Self {
+        Self {
+            inner,
+            logs: Vec::new(),
+        }
+    }
+}
 
-pub struct Simulate<'a, M, S> {
-    inner: &'a SignerMiddleware<M, S>,
+// Cheap to clone, the client, the backend, the analyses and the price cache are shared,
+// e.g. one clone per spawned worker.
+pub struct Simulate<M, S> {
+    inner: Arc<SignerMiddleware<M, S>>,
     contract: Option<Address>,
-    state_analysis: Vec<Box<dyn AnalyzeState<'a, M, S>>>,
+    wrapped_native: Option<Address>,
+    swap_router: SwapRouter,
+    oracle: Arc<PriceOracle<M, S>>,
+    backend: Arc<dyn SimulateBackend<M, S>>,
+    strategy_list: StrategyList,
+    flashloan: Flashloan<M, S>,
+    state_analysis: Vec<Arc<dyn AnalyzeState<M, S>>>,
+    replay_depth_list: Vec<usize>,
+}
+
+impl<M, S> Clone for Simulate<M, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            contract: self.contract,
+            wrapped_native: self.wrapped_native,
+            swap_router: self.swap_router,
+            oracle: self.oracle.clone(),
+            backend: self.backend.clone(),
+            strategy_list: self.strategy_list.clone(),
+            flashloan: self.flashloan.clone(),
+            state_analysis: self.state_analysis.clone(),
+            replay_depth_list: self.replay_depth_list.clone(),
+        }
+    }
 }
 
-impl<'a, M, S> Deref for Simulate<'a, M, S> {
-    type Target = &'a SignerMiddleware<M, S>;
+impl<M, S> Deref for Simulate<M, S> {
+    type Target = Arc<SignerMiddleware<M, S>>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
     }
 }
 
-impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+impl<M: Middleware + 'static, S: Signer + 'static> Simulate<M, S> {
     // can use contract as a middleware to check balance, if not increase then revert
     pub async fn init(
-        client: &'a SignerMiddleware<M, S>,
+        client: Arc<SignerMiddleware<M, S>>,
         contract: Option<Address>,
-    ) -> Result<Simulate<'a, M, S>, Box<dyn Error + 'a>> {
+    ) -> Result<Self, ArbitrageError> {
+        let chain_id = client.get_chainid().await?.as_u64();
+        let oracle = Arc::new(PriceOracle::init(client.clone()).await?);
+        let mut strategy_list = StrategyList::default();
+        // e.g., prune for native token transfer.
+        strategy_list.register(TransferStrategy);
         Ok(Self {
-            inner: client,
             contract,
+            wrapped_native: wrapped_native(chain_id),
+            swap_router: swap_router(chain_id),
+            backend: Arc::new(ParityBackend::init(client.clone()).await?),
+            strategy_list,
+            // e.g., for flashloan, loan first to ensure sufficient tokens.
+            flashloan: Flashloan::init(client.clone(), flash_lender_list(chain_id)),
             state_analysis: vec![
-                Box::new(AnalyzeEth::init(client).await?),
-                Box::new(AnalyzeToken::init(client).await?),
+                Arc::new(AnalyzeEth::init(client.clone(), oracle.clone()).await?),
+                Arc::new(AnalyzeToken::init(client.clone(), oracle.clone()).await?),
             ],
+            replay_depth_list: vec![0, 1],
+            oracle,
+            inner: client,
         })
     }
 
+    // Replace the default parity `trace_call` backend, e.g. `GethBackend` or `EvmBackend` for geth based node.
+    pub fn with_backend(mut self, backend: Box<dyn SimulateBackend<M, S>>) -> Self {
+        self.backend = backend.into();
+        self
+    }
+
+    // The call depths to replay in order, each depth is a tx list of its calls,
+    // 0 for the origin call and 1 for its subcalls by default.
+    pub fn with_replay_depth_list(mut self, depth_list: Vec<usize>) -> Self {
+        self.replay_depth_list = depth_list;
+        self
+    }
+
+    // Register, order and switch the prefilter strategies.
+    pub fn strategy_list(&mut self) -> &mut StrategyList {
+        &mut self.strategy_list
+    }
+
+    // Replace the default ERC-3156 flash lenders, tried in order.
+    pub fn with_flash_lender_list(mut self, lender_list: Vec<Address>) -> Self {
+        self.flashloan.set_lender_list(lender_list);
+        self
+    }
+
+    // Keep only the named state analysis, e.g. `["eth"]` to skip the token profit.
+    pub fn enable_analysis(&mut self, name_list: &[String]) -> Result<(), ArbitrageError> {
+        if let Some(name) = name_list
+            .iter()
+            .find(|name| !self.state_analysis.iter().any(|a| a.name() == *name))
+        {
+            return Err(ArbitrageError::Config(ConfigError::Invalid {
+                key: "analysis",
+                value: name.clone(),
+                reason: "Unknown state analysis".into(),
+            }));
+        }
+        self.state_analysis
+            .retain(|a| name_list.iter().any(|name| a.name() == name));
+        Ok(())
+    }
+
+    pub fn oracle(&self) -> &PriceOracle<M, S> {
+        &self.oracle
+    }
+
     pub async fn run(
         &self,
         tx_hash: TxHash,
         rewind: bool,
-    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, Box<dyn Error + 'a>> {
-        if let Some(tx) = self.get_transaction(tx_hash).await? {
-            let block: Option<BlockNumber> = match tx.block_number {
-                Some(block_number) if rewind => Some((block_number - 1).into()),
-                Some(block_number) if !rewind => Some(block_number.into()),
-                _ => None,
-            };
-            if let Some((trace, profit)) = self.is_valuable(tx, block).await? {
-                let tx_queue = self.to_tx_queue(&trace);
-                if tx_queue.len() > 0 {
-                    return Ok(Some((tx_queue, profit)));
-                }
-            };
+    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, ArbitrageError> {
+        match self.get_transaction(tx_hash).await? {
+            Some(tx) => self.run_tx(&tx, rewind).await,
+            None => Ok(None),
         }
+    }
 
-        Ok(None)
+    // Simulate the tx body directly, e.g. the full pending tx of the subscription, without fetching it by hash.
+    // @param rewind Simulate the mined tx on top of its parent block
+    pub async fn run_tx(
+        &self,
+        tx: &Transaction,
+        rewind: bool,
+    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, ArbitrageError> {
+        let block: Option<BlockNumber> = match tx.block_number {
+            Some(block_number) if rewind => Some((block_number - 1).into()),
+            Some(block_number) if !rewind => Some(block_number.into()),
+            _ => None,
+        };
+        self.run_tx_at(tx, block).await
     }
 
-    // Analyze whether tx is valuable according to different strategies
-    // Support customize and optimize pruning for different scene.
-    async fn is_valuable(
+    // Simulate the pending tx on top of the head instead of the latest block, which may be newer when it runs.
+    // See `HeadTracker::run_on_head` to drop it once the head is stale.
+    pub async fn run_tx_on(
+        &self,
+        tx: &Transaction,
+        head: &Head,
+    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, ArbitrageError> {
+        self.run_tx_at(tx, Some(head.number.into())).await
+    }
+
+    async fn run_tx_at(
         &self,
-        tx: Transaction,
+        tx: &Transaction,
         block: Option<BlockNumber>,
-    ) -> Result<Option<(SimulateTrace, U256)>, Box<dyn Error + 'a>> {
-        // e.g., prune for native token transfer.
-        if strategy::transfer::run(&tx) {
-            // e.g., for flashloan, loan first to ensure sufficient tokens.
-            if strategy::flashloan::run(&tx) {
-                let trace = self.to_trace(&tx, block).await?;
-
-                let analysis = self.state_analysis.iter().map(|a| async {
-                    a.run(&tx, &trace)
-                        .await
-                        .ok()
-                        .unwrap_or_default()
-                        .unwrap_or_default()
-                });
-                let profit = join_all(analysis)
-                    .await
-                    .into_iter()
-                    .map(|p| SumU256(p))
-                    .sum::<SumU256>()
-                    .0;
-
-                if !profit.is_zero() {
-                    return Ok(Some((trace, profit)));
-                }
+    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, ArbitrageError> {
+        if let Some((trace, profit, number)) = self.is_valuable(tx, block).await? {
+            let tx_queue = self.to_tx_queue(tx, &trace, number).await?;
+            if !tx_queue.is_empty() {
+                return Ok(Some((tx_queue, profit)));
             }
-        }
+        };
 
         Ok(None)
     }
 
-    async fn to_trace(
+    // Run the prefilter strategies, the rejection tells which strategy rejects the tx and why.
+    pub async fn check(&self, tx: &Transaction) -> Result<(), Rejection> {
+        self.strategy_list.run(tx).await
+    }
+
+    // Analyze whether tx is valuable according to different strategies
+    // Support customize and optimize pruning for different scene.
+    // @return `ArbitrageError::Rejected` if a prefilter strategy rejects the tx,
+    // `ArbitrageError::Analysis` if any state analysis fails
+    // @return The trace, the profit and the number of the simulated block
+    async fn is_valuable(
         &self,
         tx: &Transaction,
         block: Option<BlockNumber>,
-    ) -> Result<SimulateTrace, Box<dyn Error + 'a>> {
-        // only parity node support `trace_call`, recommend `ankr` rpc. (Sometimes it fails, need to retry)
+    ) -> Result<Option<(SimulateTrace, U256, U64)>, ArbitrageError> {
+        // e.g., prune for native token transfer, see `strategy_list` to customize.
+        self.check(tx).await.map_err(ArbitrageError::Rejected)?;
         let trace = self
-            .trace_call(tx, vec![TraceType::Trace, TraceType::StateDiff], block)
-            .await?;
+            .backend
+            .trace(tx, block)
+            .await
+            // The node is reachable but can't trace, e.g. `trace_call` isn't supported.
+            .map_err(|error| match error {
+                ArbitrageError::Rpc(error) => ArbitrageError::Trace(error),
+                error => error,
+            })?;
+
+        // The price is read at the simulated block, the latest one is resolved once for all the analyses.
+        let number = match block {
+            Some(BlockNumber::Number(number)) => number,
+            _ => self.get_block_number().await?,
+        };
+
+        // Each analysis returns the profit in native token, so token and eth profit can be added together.
+        // The failed analysis isn't regarded as no profit, e.g. the node or the oracle fails.
+        let analysis = self.state_analysis.iter().map(|a| async {
+            a.run(tx, &trace, number)
+                .await
+                .map_err(|error| match error {
+                    ArbitrageError::Analysis(error) => ArbitrageError::Analysis(error),
+                    error => ArbitrageError::analysis(error),
+                })
+        });
+        let profit = try_join_all(analysis)
+            .await?
+            .into_iter()
+            .map(|p| SumU256(p.unwrap_or_default()))
+            .sum::<SumU256>()
+            .0;
 
-        // only geth node support `debug_traceCall`
-        // let mut opts = GethDebugTracingOptions::default();
-        // opts.tracer = Some("callTracer".into());
-        // let trace = self
-        //     .debug_trace_call(&tx, block.map(|n| BlockId::Number(n)), opts)
-        //     .await?;
+        if !profit.is_zero() {
+            return Ok(Some((trace, profit, number)));
+        }
 
-        Ok(trace)
+        Ok(None)
     }
 
-    fn to_tx_queue(&self, trace: &SimulateTrace) -> Vec<Vec<TransactionRequest>> {
+    // @return A tx list per replay depth, the depth without any replayable call is skipped
+    async fn to_tx_queue(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        block: U64,
+    ) -> Result<Vec<Vec<TransactionRequest>>, ArbitrageError> {
+        // Replay the origin call, the profit of sender goes to the contract,
+        // replay the internal call, the profit of `tx.to` goes to the contract.
+        // The capital paid before being paid back is borrowed by flashloan.
+        let gains = token::gains(tx, trace);
+        let required = token::required_capital(tx, trace);
+        let of_holder = |list: &[token::TokenGain], holder: Option<Address>| {
+            list.iter()
+                .filter(|gain| Some(gain.holder) == holder)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let tree = CallTree::build(trace.trace.as_deref().unwrap_or_default())?;
         let mut tx_queue = Vec::new();
-        if let Some(trace_list) = &trace.trace {
-            let mut trace_map = HashMap::new();
-            for trace in trace_list {
-                let mut trace_key = 0;
-                for (i, v) in trace.trace_address.iter().rev().enumerate() {
-                    trace_key += v * 2_usize.pow(i.try_into().unwrap()) + 1;
-                }
-                trace_map.insert(trace_key, trace);
+        for depth in &self.replay_depth_list {
+            let mut tx_list = tree
+                .at_depth(*depth)
+                .into_iter()
+                .filter_map(|call| self.to_tx(tx, call))
+                .collect::<Vec<_>>();
+            if tx_list.is_empty() {
+                continue;
             }
+            // Only the gains of the sender and `tx.to` are analyzed, the deeper calls are made on behalf of `tx.to`.
+            let holder = match depth {
+                0 => Some(tx.from),
+                _ => tx.to,
+            };
+            tx_list.extend(
+                self.to_native_tx_list(&of_holder(&gains, holder), block)
+                    .await?,
+            );
+            tx_queue.push(
+                self.to_flashloan_tx_list(tx_list, &of_holder(&required, holder))
+                    .await,
+            );
+        }
 
-            // origin call
-            let origin_call = trace_map.get(&0).unwrap();
-            if let Some(tx) = self.to_tx(origin_call) {
-                tx_queue.push(vec![tx]);
-            }
-            // internal call
-            let mut internal_tx_list = Vec::new();
-            for i in 1..=origin_call.subtraces {
-                if let Some(tx) = self.to_tx(trace_map.get(&i).unwrap()) {
-                    internal_tx_list.push(tx);
-                } else {
-                    // Part of the trace simulation failed, can still going?
-                    // break;
-                }
-            }
-            if internal_tx_list.len() > 0 {
-                tx_queue.push(internal_tx_list);
+        Ok(tx_queue)
+    }
+
+    // The flashloan callback goes to the contract, so it's required.
+    async fn to_flashloan_tx_list(
+        &self,
+        tx_list: Vec<TransactionRequest>,
+        required: &[token::TokenGain],
+    ) -> Vec<TransactionRequest> {
+        match self.contract {
+            Some(contract) if !required.is_empty() => {
+                self.flashloan.wrap(contract, tx_list, required).await
             }
+            _ => tx_list,
         }
+    }
 
-        tx_queue
+    // Swap the token profit to native token, otherwise the contract balance check fails.
+    // The swap is called by the contract itself, so it's required.
+    async fn to_native_tx_list(
+        &self,
+        gains: &[token::TokenGain],
+        block: U64,
+    ) -> Result<Vec<TransactionRequest>, ArbitrageError> {
+        match (self.contract, self.wrapped_native) {
+            (Some(contract), Some(wrapped_native)) if !gains.is_empty() => {
+                swap::to_native_tx_list(
+                    &self.oracle,
+                    self.inner.clone(),
+                    contract,
+                    self.swap_router,
+                    wrapped_native,
+                    gains,
+                    block,
+                )
+                .await
+            }
+            _ => Ok(Vec::new()),
+        }
     }
 
-    fn to_tx(&self, trace: &TransactionTrace) -> Option<TransactionRequest> {
+    fn to_tx(&self, tx: &Transaction, trace: &TransactionTrace) -> Option<TransactionRequest> {
+        let target = self.contract.unwrap_or(self.signer().address());
         match &trace.action {
             Action::Call(data) => {
                 return Some(TransactionRequest {
@@ -170,8 +378,7 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
                     to: Some(NameOrAddress::Address(data.to)),
                     data: Some(mock_tx_data(
                         &data.input,
-                        data.from,
-                        self.contract.unwrap_or(self.signer().address()),
+                        &to_substitute(tx, data.from, Some(data.to), target),
                     )),
                     value: Some(data.value),
                     // Why is the gas obtained from the debug less than the original tx's gas limit?
@@ -188,8 +395,7 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
                 to: None,
                 data: Some(mock_tx_data(
                     &data.init,
-                    data.from,
-                    self.contract.unwrap_or(self.signer().address()),
+                    &to_substitute(tx, data.from, None, target),
                 )),
                 value: Some(data.value),
                 gas: None,
@@ -201,22 +407,46 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
     }
 }
 
-fn mock_tx_data(data: &Bytes, from: Address, to: Address) -> Bytes {
-    format!("{data:x}")
-        .replace(&format!("{from:x}"), &format!("{to:x}"))
-        .parse::<Bytes>()
-        .unwrap()
+// The caller of the replayed call, the victim sender (`tx.origin`) and the victim contract (`tx.to`) are all
+// replaced with `target`, e.g. the recipient of a swap made by the victim contract on behalf of `tx.origin`.
+// The callee keeps its own address, e.g. the router called by the victim sender.
+fn to_substitute(
+    tx: &Transaction,
+    caller: Address,
+    callee: Option<Address>,
+    target: Address,
+) -> AddressSubstitute {
+    let substitute = AddressSubstitute::default()
+        .with(caller, target)
+        .with(tx.from, target);
+    let substitute = match tx.to {
+        Some(to) => substitute.with(to, target),
+        None => substitute,
+    };
+    match callee {
+        Some(callee) => substitute.except(callee),
+        None => substitute,
+    }
+}
+
+fn mock_tx_data(data: &Bytes, substitute: &AddressSubstitute) -> Bytes {
+    substitute.run(data).0
 }
 
 #[cfg(test)]
 mod tests {
-    use super::mock_tx_data;
+    use super::{mock_tx_data, to_substitute, Simulate};
+    use crate::utils::{AddressSubstitute, ArbitrageUtil, Backrun};
+    use ethers::abi;
     use ethers::prelude::*;
 
+    fn assert_shared<T: Clone + Send + Sync + 'static>() {}
+
     #[tokio::test]
     async fn mock_tx_data_return_origin_data() {
         let data = "0x00000001".parse::<Bytes>().unwrap();
-        let parse_data = mock_tx_data(&data, Address::random(), Address::random());
+        let substitute = AddressSubstitute::default().with(Address::random(), Address::random());
+        let parse_data = mock_tx_data(&data, &substitute);
         assert_eq!(data, parse_data);
     }
 
@@ -227,11 +457,72 @@ mod tests {
         let origin_data = format!("0x00000001{}", &format!("{from:x}"))
             .parse::<Bytes>()
             .unwrap();
-        let parse_data = mock_tx_data(&origin_data, from, contract);
+        let substitute = AddressSubstitute::default().with(from, contract);
+        let parse_data = mock_tx_data(&origin_data, &substitute);
         assert!(origin_data != parse_data);
         assert_eq!(
             format!("{parse_data:x}"),
             format!("0x00000001{}", &format!("{contract:x}"))
         );
     }
+
+    #[tokio::test]
+    async fn mock_tx_data_skip_unaligned_hex() {
+        // The hex digits of `from` span the byte boundaries, it isn't an address in the data.
+        let from = "0x1111111111111111111111111111111111111110"
+            .parse::<Address>()
+            .unwrap();
+        let origin_data = format!("0x0{from:x}0").parse::<Bytes>().unwrap();
+        let substitute = AddressSubstitute::default().with(from, Address::random());
+        assert_eq!(mock_tx_data(&origin_data, &substitute), origin_data);
+    }
+
+    #[tokio::test]
+    async fn mock_tx_data_replace_origin_and_victim_contract() {
+        let tx = Transaction {
+            from: Address::random(),
+            to: Some(Address::random()),
+            ..Default::default()
+        };
+        let pool = Address::random();
+        let contract = Address::random();
+        // The victim contract calls `pool.swap(amount0Out, amount1Out, to, data)` for `tx.origin`.
+        let mut origin_data = vec![0x02, 0x2c, 0x0d, 0x9f];
+        origin_data.extend(abi::encode(&[
+            abi::Token::Uint(U256::zero()),
+            abi::Token::Uint(U256::one()),
+            abi::Token::Address(tx.from),
+            abi::Token::Bytes(tx.to.unwrap().as_bytes().to_vec()),
+        ]));
+
+        let substitute = to_substitute(&tx, tx.to.unwrap(), Some(pool), contract);
+        let (parse_data, substitution_list) = substitute.run(&origin_data);
+        assert_eq!(substitution_list.len(), 2);
+        assert_eq!(parse_data, mock_tx_data(&origin_data.into(), &substitute));
+        let mut data = vec![0x02, 0x2c, 0x0d, 0x9f];
+        data.extend(abi::encode(&[
+            abi::Token::Uint(U256::zero()),
+            abi::Token::Uint(U256::one()),
+            abi::Token::Address(contract),
+            abi::Token::Bytes(contract.as_bytes().to_vec()),
+        ]));
+        assert_eq!(parse_data.to_vec(), data);
+
+        // The callee keeps its address.
+        let substitute = to_substitute(&tx, tx.from, tx.to, contract);
+        assert_eq!(
+            substitute
+                .run(&abi::encode(&[abi::Token::Address(tx.to.unwrap())]))
+                .1,
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    async fn share_across_task() {
+        // Cloned into each spawned worker, see the binary.
+        assert_shared::<Simulate<Provider<Http>, LocalWallet>>();
+        assert_shared::<ArbitrageUtil<Provider<Http>, LocalWallet>>();
+        assert_shared::<Backrun<Provider<Http>, LocalWallet>>();
+    }
 }
@@ -0,0 +1,759 @@
+use super::SimulateBackend;
+use crate::utils::{ArbitrageError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::{middleware::signer::SignerMiddlewareError, prelude::*, utils::keccak256};
+use futures::{future::join_all, try_join};
+use revm::{
+    inspector_handle_register,
+    interpreter::{
+        CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, InstructionResult,
+        InterpreterResult,
+    },
+    primitives::{self, AccountInfo, Bytecode, CreateScheme, ExecutionResult, TransactTo},
+    Database, Evm, EvmContext, Inspector,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+// Every round only finds the state read before the first missing one takes effect,
+// a deep call tree needs more rounds.
+const MAX_FETCH_ROUND: usize = 128;
+
+// Run the tx in an in-process EVM over the state forked from any json-rpc node.
+// The EVM is sync, so the state is fetched speculatively: the missing state reads as empty
+// and is recorded, then fetched concurrently and the tx is run again, until nothing is missing.
+pub struct EvmBackend<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+    chain_id: u64,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static, S: Signer + 'static> SimulateBackend<M, S> for EvmBackend<M, S> {
+    async fn init(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError> {
+        let chain_id = client.get_chainid().await?.as_u64();
+        Ok(Self { client, chain_id })
+    }
+
+    async fn trace(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, ArbitrageError> {
+        // Pin the block number, so all the state is read from the same block.
+        let parent = self
+            .client
+            .get_block(block.unwrap_or(BlockNumber::Latest))
+            .await?
+            .ok_or_else(|| ArbitrageError::rpc("Block not found"))?;
+        let number = parent
+            .number
+            .ok_or_else(|| ArbitrageError::rpc("Block is pending"))?;
+        // Replay in the next block if it is mined, otherwise mock it from the parent.
+        let next = match self.client.get_block(number + 1).await? {
+            Some(next) => next,
+            None => Block {
+                number: Some(number + 1),
+                timestamp: parent.timestamp + 1,
+                base_fee_per_gas: parent.next_block_base_fee(),
+                ..parent
+            },
+        };
+        let env = to_env(self.chain_id, tx, &next);
+
+        let mut db = ForkDB::default();
+        db.missing.accounts.insert(tx.from);
+        db.missing.accounts.insert(next.author.unwrap_or_default());
+        if let Some(to) = tx.to {
+            db.missing.accounts.insert(to);
+        }
+
+        for _ in 0..MAX_FETCH_ROUND {
+            self.fetch(&mut db, number.into()).await?;
+            let (result, inspector) = execute(&mut db, env.clone());
+            if db.missing.is_empty() {
+                return Ok(to_trace(
+                    result.map_err(|error| ArbitrageError::Trace(Box::new(error)))?,
+                    inspector,
+                    &db,
+                ));
+            }
+        }
+
+        Err(ArbitrageError::trace(
+            "Too many rounds to fetch the forked state",
+        ))
+    }
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> EvmBackend<M, S> {
+    async fn fetch(&self, db: &mut ForkDB, block: BlockId) -> Result<(), ArbitrageError> {
+        let missing = std::mem::take(&mut db.missing);
+
+        let account_list = join_all(missing.accounts.into_iter().map(|address| async move {
+            let (balance, nonce, code) = try_join!(
+                self.client.get_balance(address, Some(block)),
+                self.client.get_transaction_count(address, Some(block)),
+                self.client.get_code(address, Some(block)),
+            )?;
+            Ok::<_, SignerMiddlewareError<M, S>>((address, balance, nonce, code))
+        }))
+        .await;
+        for account in account_list {
+            let (address, balance, nonce, code) = account?;
+            let info = AccountInfo::new(
+                balance.convert(),
+                nonce.as_u64(),
+                if code.is_empty() {
+                    primitives::KECCAK_EMPTY
+                } else {
+                    H256::from(keccak256(&code)).convert()
+                },
+                Bytecode::new_raw(code.convert()),
+            );
+            // The empty account is regarded as not existing.
+            db.accounts
+                .insert(address, (!info.is_empty()).then_some(info));
+        }
+
+        let storage_list = join_all(missing.storage.into_iter().map(
+            |(address, slot)| async move {
+                let mut location = H256::zero();
+                slot.to_big_endian(location.as_bytes_mut());
+                let value = self
+                    .client
+                    .get_storage_at(address, location, Some(block))
+                    .await?;
+                Ok::<_, SignerMiddlewareError<M, S>>((address, slot, value))
+            },
+        ))
+        .await;
+        for storage in storage_list {
+            let (address, slot, value) = storage?;
+            db.storage
+                .insert((address, slot), U256::from_big_endian(value.as_bytes()));
+        }
+
+        let block_hash_list = join_all(missing.block_hashes.into_iter().map(|number| async move {
+            let block = self.client.get_block(number.as_u64()).await?;
+            Ok::<_, SignerMiddlewareError<M, S>>((number, block.and_then(|block| block.hash)))
+        }))
+        .await;
+        for block_hash in block_hash_list {
+            let (number, hash) = block_hash?;
+            db.block_hashes.insert(number, hash.unwrap_or_default());
+        }
+
+        Ok(())
+    }
+}
+
+fn to_env(chain_id: u64, tx: &Transaction, block: &Block<TxHash>) -> primitives::Env {
+    let mut env = primitives::Env::default();
+    env.cfg.chain_id = chain_id;
+
+    env.block.number = primitives::U256::from(block.number.unwrap_or_default().as_u64());
+    env.block.coinbase = block.author.unwrap_or_default().convert();
+    env.block.timestamp = block.timestamp.convert();
+    env.block.gas_limit = block.gas_limit.convert();
+    env.block.basefee = block.base_fee_per_gas.unwrap_or_default().convert();
+    env.block.difficulty = block.difficulty.convert();
+    env.block.prevrandao = Some(block.mix_hash.unwrap_or_default().convert());
+
+    env.tx.caller = tx.from.convert();
+    env.tx.gas_limit = tx.gas.as_u64();
+    // Like `trace_call`, the tx below the base fee still runs, so the base fee check is off
+    // and the eip-1559 tx pays its effective gas price, min(max fee, base fee + priority fee).
+    env.cfg.disable_base_fee = true;
+    let base_fee = block.base_fee_per_gas.unwrap_or_default();
+    env.tx.gas_price = match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+        (Some(max_fee), Some(priority_fee)) => max_fee.min(base_fee.saturating_add(priority_fee)),
+        _ => tx.gas_price.or(tx.max_fee_per_gas).unwrap_or_default(),
+    }
+    .convert();
+    env.tx.transact_to = match tx.to {
+        Some(to) => TransactTo::Call(to.convert()),
+        None => TransactTo::Create(CreateScheme::Create),
+    };
+    env.tx.value = tx.value.convert();
+    env.tx.data = tx.input.clone().convert();
+    // Like `trace_call`, the nonce is not checked, the invalid nonce is found in the state diff.
+    env.tx.nonce = None;
+    env.tx.access_list = tx
+        .access_list
+        .iter()
+        .flat_map(|access_list| &access_list.0)
+        .map(|item| {
+            (
+                item.address.convert(),
+                item.storage_keys
+                    .iter()
+                    .map(|key| U256::from_big_endian(key.as_bytes()).convert())
+                    .collect(),
+            )
+        })
+        .collect();
+
+    env
+}
+
+fn execute(
+    db: &mut ForkDB,
+    env: primitives::Env,
+) -> (
+    Result<primitives::ResultAndState, primitives::EVMError<Infallible>>,
+    TraceInspector,
+) {
+    let mut inspector = TraceInspector::default();
+    let result = Evm::builder()
+        .with_db(db)
+        .with_external_context(&mut inspector)
+        .with_env(Box::new(env))
+        .append_handler_register(inspector_handle_register)
+        .build()
+        .transact();
+
+    (result, inspector)
+}
+
+fn to_trace(
+    result: primitives::ResultAndState,
+    inspector: TraceInspector,
+    db: &ForkDB,
+) -> SimulateTrace {
+    let (output, logs) = match result.result {
+        ExecutionResult::Success { output, logs, .. } => (output.into_data(), logs),
+        ExecutionResult::Revert { output, .. } => (output, Vec::new()),
+        ExecutionResult::Halt { .. } => (primitives::Bytes::new(), Vec::new()),
+    };
+
+    SimulateTrace {
+        inner: BlockTrace {
+            output: output.convert(),
+            trace: Some(inspector.trace_list),
+            vm_trace: None,
+            state_diff: Some(to_state_diff(&result.state, db)),
+            transaction_hash: None,
+        },
+        logs: logs
+            .into_iter()
+            .map(|log| Log {
+                address: log.address.convert(),
+                topics: log.topics().iter().map(|topic| topic.convert()).collect(),
+                data: log.data.data.convert(),
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+// Diff the executed state with the forked state, the same as parity `stateDiff`.
+fn to_state_diff(state: &primitives::State, db: &ForkDB) -> StateDiff {
+    let mut state_diff = BTreeMap::new();
+    for (address, account) in state {
+        if !account.is_touched() {
+            continue;
+        }
+        let address = address.convert();
+        let before = db.accounts.get(&address).cloned().flatten();
+        let after = (!account.is_selfdestructed() && !account.is_empty()).then_some(&account.info);
+
+        let account_diff = match (before, after) {
+            (None, None) => continue,
+            (None, Some(after)) => AccountDiff {
+                balance: Diff::Born(after.balance.convert()),
+                nonce: Diff::Born(after.nonce.into()),
+                code: Diff::Born(to_code(after)),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| !slot.present_value.is_zero())
+                    .map(|(key, slot)| (to_slot(key), Diff::Born(to_slot(&slot.present_value))))
+                    .collect(),
+            },
+            (Some(before), None) => AccountDiff {
+                balance: Diff::Died(before.balance.convert()),
+                nonce: Diff::Died(before.nonce.into()),
+                code: Diff::Died(to_code(&before)),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| !slot.previous_or_original_value.is_zero())
+                    .map(|(key, slot)| {
+                        (
+                            to_slot(key),
+                            Diff::Died(to_slot(&slot.previous_or_original_value)),
+                        )
+                    })
+                    .collect(),
+            },
+            (Some(before), Some(after)) => AccountDiff {
+                balance: to_diff(before.balance.convert(), after.balance.convert()),
+                nonce: to_diff(before.nonce.into(), after.nonce.into()),
+                code: to_diff(to_code(&before), to_code(after)),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| slot.is_changed())
+                    .map(|(key, slot)| {
+                        (
+                            to_slot(key),
+                            to_diff(
+                                to_slot(&slot.previous_or_original_value),
+                                to_slot(&slot.present_value),
+                            ),
+                        )
+                    })
+                    .collect(),
+            },
+        };
+
+        let unchanged = matches!(account_diff.balance, Diff::Same)
+            && matches!(account_diff.nonce, Diff::Same)
+            && matches!(account_diff.code, Diff::Same)
+            && account_diff.storage.is_empty();
+        if !unchanged {
+            state_diff.insert(address, account_diff);
+        }
+    }
+
+    StateDiff(state_diff)
+}
+
+fn to_diff<T: PartialEq>(from: T, to: T) -> Diff<T> {
+    if from == to {
+        Diff::Same
+    } else {
+        Diff::Changed(ChangedType { from, to })
+    }
+}
+
+fn to_code(info: &AccountInfo) -> Bytes {
+    info.code
+        .as_ref()
+        .map(|code| code.original_bytes().convert())
+        .unwrap_or_default()
+}
+
+fn to_slot(value: &primitives::U256) -> H256 {
+    H256::from(value.to_be_bytes::<32>())
+}
+
+#[derive(Default)]
+struct MissingState {
+    accounts: BTreeSet<Address>,
+    storage: BTreeSet<(Address, U256)>,
+    block_hashes: BTreeSet<U256>,
+}
+
+impl MissingState {
+    fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.storage.is_empty() && self.block_hashes.is_empty()
+    }
+}
+
+// The state forked from the node, `None` account means not existing.
+#[derive(Default)]
+struct ForkDB {
+    accounts: HashMap<Address, Option<AccountInfo>>,
+    storage: HashMap<(Address, U256), U256>,
+    block_hashes: HashMap<U256, H256>,
+    missing: MissingState,
+}
+
+impl Database for ForkDB {
+    // The missing state is recorded instead of failing.
+    type Error = Infallible;
+
+    fn basic(&mut self, address: primitives::Address) -> Result<Option<AccountInfo>, Infallible> {
+        let address = address.convert();
+        match self.accounts.get(&address) {
+            Some(info) => Ok(info.clone()),
+            None => {
+                self.missing.accounts.insert(address);
+                Ok(None)
+            }
+        }
+    }
+
+    // The code is always loaded with the account.
+    fn code_by_hash(&mut self, code_hash: primitives::B256) -> Result<Bytecode, Infallible> {
+        Ok(self
+            .accounts
+            .values()
+            .flatten()
+            .find(|info| info.code_hash == code_hash)
+            .and_then(|info| info.code.clone())
+            .unwrap_or_default())
+    }
+
+    fn storage(
+        &mut self,
+        address: primitives::Address,
+        index: primitives::U256,
+    ) -> Result<primitives::U256, Infallible> {
+        let key = (address.convert(), index.convert());
+        match self.storage.get(&key) {
+            Some(value) => Ok(value.convert()),
+            None => {
+                self.missing.storage.insert(key);
+                Ok(primitives::U256::ZERO)
+            }
+        }
+    }
+
+    fn block_hash(&mut self, number: primitives::U256) -> Result<primitives::B256, Infallible> {
+        let number = number.convert();
+        match self.block_hashes.get(&number) {
+            Some(hash) => Ok(hash.convert()),
+            None => {
+                self.missing.block_hashes.insert(number);
+                Ok(primitives::B256::ZERO)
+            }
+        }
+    }
+}
+
+// Record the call tree in parity `trace` shape.
+#[derive(Default)]
+struct TraceInspector {
+    trace_list: Vec<TransactionTrace>,
+    // index of the calls not ended yet
+    call_stack: Vec<usize>,
+}
+
+impl TraceInspector {
+    fn push(&mut self, action: Action, action_type: ActionType) -> usize {
+        let trace_address = match self.call_stack.last() {
+            Some(&parent) => {
+                let parent = &mut self.trace_list[parent];
+                let mut trace_address = parent.trace_address.clone();
+                trace_address.push(parent.subtraces);
+                parent.subtraces += 1;
+                trace_address
+            }
+            None => Vec::new(),
+        };
+        self.trace_list.push(TransactionTrace {
+            trace_address,
+            subtraces: 0,
+            action,
+            action_type,
+            result: None,
+            error: None,
+        });
+        self.trace_list.len() - 1
+    }
+
+    fn end(&mut self, result: &InterpreterResult, to_res: impl FnOnce() -> Res) {
+        if let Some(index) = self.call_stack.pop() {
+            let trace = &mut self.trace_list[index];
+            if result.result.is_ok() {
+                trace.result = Some(to_res());
+            } else {
+                trace.error = Some(to_error(result.result));
+            }
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for TraceInspector {
+    fn call(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let (from, call_type, value) = match inputs.context.scheme {
+            CallScheme::Call => (inputs.context.caller, CallType::Call, inputs.transfer.value),
+            CallScheme::StaticCall => (
+                inputs.context.caller,
+                CallType::StaticCall,
+                inputs.transfer.value,
+            ),
+            // The code runs in the context of the current contract.
+            CallScheme::CallCode => (
+                inputs.context.address,
+                CallType::CallCode,
+                inputs.transfer.value,
+            ),
+            CallScheme::DelegateCall => (
+                inputs.context.address,
+                CallType::DelegateCall,
+                inputs.context.apparent_value,
+            ),
+        };
+        let index = self.push(
+            Action::Call(Call {
+                from: from.convert(),
+                to: inputs.contract.convert(),
+                value: value.convert(),
+                gas: inputs.gas_limit.into(),
+                input: inputs.input.clone().convert(),
+                call_type,
+            }),
+            ActionType::Call,
+        );
+        self.call_stack.push(index);
+
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.end(&outcome.result, || {
+            Res::Call(CallResult {
+                gas_used: outcome.result.gas.spend().into(),
+                output: outcome.result.output.clone().convert(),
+            })
+        });
+
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let index = self.push(
+            Action::Create(Create {
+                from: inputs.caller.convert(),
+                value: inputs.value.convert(),
+                gas: inputs.gas_limit.into(),
+                init: inputs.init_code.clone().convert(),
+            }),
+            ActionType::Create,
+        );
+        self.call_stack.push(index);
+
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.end(&outcome.result, || {
+            Res::Create(CreateResult {
+                gas_used: outcome.result.gas.spend().into(),
+                code: outcome.result.output.clone().convert(),
+                address: outcome.address.unwrap_or_default().convert(),
+            })
+        });
+
+        outcome
+    }
+
+    fn selfdestruct(
+        &mut self,
+        contract: primitives::Address,
+        target: primitives::Address,
+        value: primitives::U256,
+    ) {
+        self.push(
+            Action::Suicide(Suicide {
+                address: contract.convert(),
+                refund_address: target.convert(),
+                balance: value.convert(),
+            }),
+            ActionType::Suicide,
+        );
+    }
+}
+
+// Same as the error message of parity trace.
+fn to_error(result: InstructionResult) -> String {
+    match result {
+        InstructionResult::Revert => "Reverted".into(),
+        InstructionResult::OutOfGas
+        | InstructionResult::MemoryOOG
+        | InstructionResult::MemoryLimitOOG
+        | InstructionResult::PrecompileOOG
+        | InstructionResult::InvalidOperandOOG => "Out of gas".into(),
+        result => format!("{result:?}"),
+    }
+}
+
+// Convert between the ethers and revm primitive types.
+trait Convert<T> {
+    fn convert(self) -> T;
+}
+
+impl Convert<primitives::Address> for Address {
+    fn convert(self) -> primitives::Address {
+        primitives::Address::from(self.0)
+    }
+}
+
+impl Convert<Address> for primitives::Address {
+    fn convert(self) -> Address {
+        Address::from_slice(self.as_slice())
+    }
+}
+
+impl Convert<primitives::U256> for U256 {
+    fn convert(self) -> primitives::U256 {
+        let mut bytes = [0; 32];
+        self.to_big_endian(&mut bytes);
+        primitives::U256::from_be_bytes(bytes)
+    }
+}
+
+impl Convert<U256> for primitives::U256 {
+    fn convert(self) -> U256 {
+        U256::from_big_endian(&self.to_be_bytes::<32>())
+    }
+}
+
+impl Convert<primitives::B256> for H256 {
+    fn convert(self) -> primitives::B256 {
+        primitives::B256::from(self.0)
+    }
+}
+
+impl Convert<H256> for primitives::B256 {
+    fn convert(self) -> H256 {
+        H256::from(self.0)
+    }
+}
+
+impl Convert<primitives::Bytes> for Bytes {
+    fn convert(self) -> primitives::Bytes {
+        primitives::Bytes::from(self.to_vec())
+    }
+}
+
+impl Convert<Bytes> for primitives::Bytes {
+    fn convert(self) -> Bytes {
+        Bytes::from(self.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, to_env, to_trace, Convert, ForkDB};
+    use ethers::prelude::*;
+    use revm::primitives::{self, AccountInfo, Bytecode};
+
+    #[tokio::test]
+    async fn execute_until_no_missing_state() {
+        let caller = Address::random();
+        let contract = Address::random();
+        // PUSH1 0x2a PUSH1 0x00 SSTORE STOP
+        let bytecode = Bytecode::new_raw("0x602a60005500".parse::<Bytes>().unwrap().convert());
+
+        let mut db = ForkDB::default();
+        db.accounts.insert(
+            caller,
+            Some(AccountInfo::from_balance(U256::exp10(18).convert())),
+        );
+        db.accounts.insert(
+            contract,
+            Some(AccountInfo::new(
+                primitives::U256::ZERO,
+                1,
+                bytecode.hash_slow(),
+                bytecode,
+            )),
+        );
+        // coinbase
+        db.accounts.insert(Address::zero(), None);
+
+        let mut env = primitives::Env::default();
+        env.tx.caller = caller.convert();
+        env.tx.transact_to = primitives::TransactTo::Call(contract.convert());
+        env.block.prevrandao = Some(primitives::B256::ZERO);
+
+        // The missing slot reads as zero and is recorded.
+        execute(&mut db, env.clone()).0.unwrap();
+        assert_eq!(
+            db.missing.storage.iter().cloned().collect::<Vec<_>>(),
+            vec![(contract, U256::zero())]
+        );
+
+        db.missing = Default::default();
+        db.storage.insert((contract, U256::zero()), U256::one());
+        let (result, inspector) = execute(&mut db, env);
+        assert!(db.missing.is_empty());
+
+        let trace = to_trace(result.unwrap(), inspector, &db);
+        let trace_list = trace.trace.as_ref().unwrap();
+        assert_eq!(trace_list.len(), 1);
+        assert!(trace_list[0].trace_address.is_empty());
+        assert!(trace_list[0].error.is_none());
+
+        let state_diff = &trace.state_diff.as_ref().unwrap().0;
+        assert_eq!(
+            state_diff[&contract].storage[&H256::zero()],
+            Diff::Changed(ChangedType {
+                from: H256::from_low_u64_be(1),
+                to: H256::from_low_u64_be(0x2a),
+            })
+        );
+        assert_eq!(
+            state_diff[&caller].nonce,
+            Diff::Changed(ChangedType {
+                from: 0.into(),
+                to: 1.into(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn pay_effective_gas_price() {
+        let gwei = U256::exp10(9);
+        let block = Block {
+            number: Some(10.into()),
+            author: Some(Address::random()),
+            gas_limit: U256::from(30_000_000),
+            base_fee_per_gas: Some(gwei * 20),
+            mix_hash: Some(H256::zero()),
+            ..Default::default()
+        };
+        // The max fee is below the base fee, which `trace_call` still runs.
+        let mut tx = Transaction {
+            from: Address::random(),
+            to: Some(Address::random()),
+            gas: U256::from(100_000),
+            gas_price: Some(gwei * 10),
+            max_fee_per_gas: Some(gwei * 10),
+            max_priority_fee_per_gas: Some(gwei),
+            ..Default::default()
+        };
+
+        let mut db = ForkDB::default();
+        db.accounts.insert(
+            tx.from,
+            Some(AccountInfo::from_balance(U256::exp10(18).convert())),
+        );
+        db.accounts.insert(tx.to.unwrap(), None);
+        db.accounts.insert(block.author.unwrap(), None);
+
+        let env = to_env(1, &tx, &block);
+        assert_eq!(env.tx.gas_price.convert(), gwei * 10);
+        let (result, inspector) = execute(&mut db, env);
+        assert!(db.missing.is_empty());
+        let trace = to_trace(result.unwrap(), inspector, &db);
+        assert_eq!(
+            trace.state_diff.as_ref().unwrap().0[&tx.from].balance,
+            Diff::Changed(ChangedType {
+                from: U256::exp10(18),
+                to: U256::exp10(18) - gwei * 10 * 21_000,
+            })
+        );
+
+        // Otherwise the base fee plus the priority fee.
+        tx.max_fee_per_gas = Some(gwei * 30);
+        assert_eq!(to_env(1, &tx, &block).tx.gas_price.convert(), gwei * 21);
+        // The legacy tx pays its gas price.
+        tx.max_fee_per_gas = None;
+        tx.max_priority_fee_per_gas = None;
+        assert_eq!(to_env(1, &tx, &block).tx.gas_price.convert(), gwei * 10);
+    }
+}
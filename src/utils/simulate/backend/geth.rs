@@ -0,0 +1,399 @@
+use super::SimulateBackend;
+use crate::utils::{ArbitrageError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use futures::try_join;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+// Only geth node support `debug_traceCall`, e.g. bsc and polygon node.
+// The `callTracer` and `prestateTracer` (diff mode) output is converted to parity shape.
+pub struct GethBackend<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type")]
+    call_type: String,
+    from: Address,
+    to: Option<Address>,
+    value: Option<U256>,
+    gas: U256,
+    gas_used: U256,
+    input: Bytes,
+    output: Option<Bytes>,
+    error: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+    #[serde(default)]
+    logs: Vec<CallLog>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CallLog {
+    address: Address,
+    topics: Vec<H256>,
+    data: Bytes,
+    // index of the sub call that the log is emitted before
+    position: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PrestateDiff {
+    pre: BTreeMap<Address, AccountState>,
+    post: BTreeMap<Address, AccountState>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct AccountState {
+    balance: Option<U256>,
+    nonce: Option<u64>,
+    code: Option<Bytes>,
+    #[serde(default)]
+    storage: BTreeMap<H256, H256>,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static, S: Signer + 'static> SimulateBackend<M, S> for GethBackend<M, S> {
+    async fn init(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError> {
+        Ok(Self { client })
+    }
+
+    async fn trace(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, ArbitrageError> {
+        // Geth rejects the call with both `gasPrice` and `maxFeePerGas`, so send a legacy call.
+        let call = TransactionRequest {
+            from: Some(tx.from),
+            to: tx.to.map(NameOrAddress::Address),
+            gas: Some(tx.gas),
+            gas_price: tx.gas_price.or(tx.max_fee_per_gas),
+            value: Some(tx.value),
+            data: Some(tx.input.clone()),
+            ..Default::default()
+        };
+        let block = block.unwrap_or(BlockNumber::Latest);
+
+        let provider = self.client.provider();
+        let (call_frame, prestate_diff): (CallFrame, PrestateDiff) = try_join!(
+            provider.request(
+                "debug_traceCall",
+                (
+                    &call,
+                    block,
+                    json!({ "tracer": "callTracer", "tracerConfig": { "withLog": true } }),
+                ),
+            ),
+            provider.request(
+                "debug_traceCall",
+                (
+                    &call,
+                    block,
+                    json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } }),
+                ),
+            ),
+        )?;
+
+        let mut trace_list = Vec::new();
+        to_trace_list(&call_frame, Vec::new(), &mut trace_list);
+        let mut logs = Vec::new();
+        to_logs(&call_frame, &mut logs);
+
+        Ok(SimulateTrace {
+            inner: BlockTrace {
+                output: call_frame.output.clone().unwrap_or_default(),
+                trace: Some(trace_list),
+                vm_trace: None,
+                state_diff: Some(to_state_diff(prestate_diff)),
+                transaction_hash: None,
+            },
+            logs,
+        })
+    }
+}
+
+// Flatten the call tree in pre-order, the same as parity `trace`.
+fn to_trace_list(
+    frame: &CallFrame,
+    trace_address: Vec<usize>,
+    trace_list: &mut Vec<TransactionTrace>,
+) {
+    let to = frame.to.unwrap_or_default();
+    let value = frame.value.unwrap_or_default();
+    let output = frame.output.clone().unwrap_or_default();
+    let call = |call_type| {
+        (
+            Action::Call(Call {
+                from: frame.from,
+                to,
+                value,
+                gas: frame.gas,
+                input: frame.input.clone(),
+                call_type,
+            }),
+            ActionType::Call,
+            Res::Call(CallResult {
+                gas_used: frame.gas_used,
+                output: output.clone(),
+            }),
+        )
+    };
+    let (action, action_type, result) = match frame.call_type.as_str() {
+        "CALL" => call(CallType::Call),
+        "STATICCALL" => call(CallType::StaticCall),
+        "DELEGATECALL" => call(CallType::DelegateCall),
+        "CALLCODE" => call(CallType::CallCode),
+        "CREATE" | "CREATE2" => (
+            Action::Create(Create {
+                from: frame.from,
+                value,
+                gas: frame.gas,
+                init: frame.input.clone(),
+            }),
+            ActionType::Create,
+            Res::Create(CreateResult {
+                gas_used: frame.gas_used,
+                code: output.clone(),
+                address: to,
+            }),
+        ),
+        "SELFDESTRUCT" => (
+            Action::Suicide(Suicide {
+                address: frame.from,
+                refund_address: to,
+                balance: value,
+            }),
+            ActionType::Suicide,
+            Res::None,
+        ),
+        _ => call(CallType::None),
+    };
+
+    trace_list.push(TransactionTrace {
+        trace_address: trace_address.clone(),
+        subtraces: frame.calls.len(),
+        action,
+        action_type,
+        result: frame.error.is_none().then_some(result),
+        error: frame.error.as_ref().map(|error| match error.as_str() {
+            "execution reverted" => "Reverted".into(),
+            "out of gas" => "Out of gas".into(),
+            error => error.into(),
+        }),
+    });
+    for (i, call) in frame.calls.iter().enumerate() {
+        let mut trace_address = trace_address.clone();
+        trace_address.push(i);
+        to_trace_list(call, trace_address, trace_list);
+    }
+}
+
+// Collect the logs in the emitted order, the logs of reverted calls are dropped.
+fn to_logs(frame: &CallFrame, logs: &mut Vec<Log>) {
+    if frame.error.is_some() {
+        return;
+    }
+
+    let to_log = |log: &CallLog| Log {
+        address: log.address,
+        topics: log.topics.clone(),
+        data: log.data.clone(),
+        ..Default::default()
+    };
+    let mut frame_logs = frame.logs.iter().peekable();
+    for (i, call) in frame.calls.iter().enumerate() {
+        while let Some(log) =
+            frame_logs.next_if(|log| !matches!(log.position, Some(position) if position > i))
+        {
+            logs.push(to_log(log));
+        }
+        to_logs(call, logs);
+    }
+    logs.extend(frame_logs.map(to_log));
+}
+
+// The pre state has the modified accounts, and the post state only has the modified fields.
+// The account only in pre state is deleted, and the one only in post state is created.
+fn to_state_diff(prestate_diff: PrestateDiff) -> StateDiff {
+    let PrestateDiff { pre, post } = prestate_diff;
+    let address_list = pre
+        .keys()
+        .chain(post.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    let mut state_diff = BTreeMap::new();
+    for address in address_list {
+        let account_diff = match (pre.get(&address), post.get(&address)) {
+            (Some(pre), None) => AccountDiff {
+                balance: Diff::Died(pre.balance.unwrap_or_default()),
+                nonce: Diff::Died(pre.nonce.unwrap_or_default().into()),
+                code: Diff::Died(pre.code.clone().unwrap_or_default()),
+                storage: pre
+                    .storage
+                    .iter()
+                    .map(|(slot, value)| (*slot, Diff::Died(*value)))
+                    .collect(),
+            },
+            (None, Some(post)) => AccountDiff {
+                balance: Diff::Born(post.balance.unwrap_or_default()),
+                nonce: Diff::Born(post.nonce.unwrap_or_default().into()),
+                code: Diff::Born(post.code.clone().unwrap_or_default()),
+                storage: post
+                    .storage
+                    .iter()
+                    .map(|(slot, value)| (*slot, Diff::Born(*value)))
+                    .collect(),
+            },
+            (Some(pre), Some(post)) => {
+                // The slot reset to zero is omitted in the post state.
+                let slot_list = pre.storage.keys().chain(post.storage.keys());
+                AccountDiff {
+                    balance: to_diff(pre.balance.unwrap_or_default(), post.balance),
+                    nonce: to_diff(
+                        pre.nonce.unwrap_or_default().into(),
+                        post.nonce.map(U256::from),
+                    ),
+                    code: to_diff(pre.code.clone().unwrap_or_default(), post.code.clone()),
+                    storage: slot_list
+                        .map(|slot| {
+                            (
+                                *slot,
+                                to_diff(
+                                    pre.storage.get(slot).copied().unwrap_or_default(),
+                                    Some(post.storage.get(slot).copied().unwrap_or_default()),
+                                ),
+                            )
+                        })
+                        .filter(|(_, diff)| !matches!(diff, Diff::Same))
+                        .collect(),
+                }
+            }
+            (None, None) => continue,
+        };
+        state_diff.insert(address, account_diff);
+    }
+
+    StateDiff(state_diff)
+}
+
+fn to_diff<T: PartialEq>(from: T, to: Option<T>) -> Diff<T> {
+    match to {
+        Some(to) if to != from => Diff::Changed(ChangedType { from, to }),
+        _ => Diff::Same,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_state_diff, to_trace_list, CallFrame, PrestateDiff};
+    use ethers::prelude::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn call_frame_to_trace_list() {
+        let frame: CallFrame = serde_json::from_value(json!({
+            "type": "CALL",
+            "from": "0x0000000000000000000000000000000000000001",
+            "to": "0x0000000000000000000000000000000000000002",
+            "value": "0x1",
+            "gas": "0x5208",
+            "gasUsed": "0x5208",
+            "input": "0x",
+            "calls": [
+                {
+                    "type": "DELEGATECALL",
+                    "from": "0x0000000000000000000000000000000000000002",
+                    "to": "0x0000000000000000000000000000000000000003",
+                    "gas": "0x100",
+                    "gasUsed": "0x100",
+                    "input": "0x01",
+                    "error": "execution reverted",
+                },
+                {
+                    "type": "STATICCALL",
+                    "from": "0x0000000000000000000000000000000000000002",
+                    "to": "0x0000000000000000000000000000000000000004",
+                    "gas": "0x100",
+                    "gasUsed": "0x10",
+                    "input": "0x02",
+                    "output": "0x03",
+                },
+            ],
+        }))
+        .unwrap();
+
+        let mut trace_list = Vec::new();
+        to_trace_list(&frame, Vec::new(), &mut trace_list);
+        assert_eq!(
+            trace_list
+                .iter()
+                .map(|trace| (trace.trace_address.clone(), trace.subtraces))
+                .collect::<Vec<_>>(),
+            vec![(vec![], 2), (vec![0], 0), (vec![1], 0)]
+        );
+        assert_eq!(trace_list[1].error, Some("Reverted".into()));
+        match &trace_list[2].action {
+            Action::Call(call) => assert_eq!(call.call_type, CallType::StaticCall),
+            _ => panic!("Expect call action"),
+        }
+    }
+
+    #[tokio::test]
+    async fn prestate_diff_to_state_diff() {
+        let prestate_diff: PrestateDiff = serde_json::from_value(json!({
+            "pre": {
+                "0x0000000000000000000000000000000000000001": {
+                    "balance": "0x10",
+                    "nonce": 1,
+                },
+                "0x0000000000000000000000000000000000000002": {
+                    "balance": "0x0",
+                    "code": "0x00",
+                    "storage": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000000":
+                            "0x0000000000000000000000000000000000000000000000000000000000000001",
+                    },
+                },
+            },
+            "post": {
+                "0x0000000000000000000000000000000000000001": {
+                    "balance": "0x8",
+                    "nonce": 2,
+                },
+                "0x0000000000000000000000000000000000000002": {},
+            },
+        }))
+        .unwrap();
+
+        let state_diff = to_state_diff(prestate_diff).0;
+        let sender = state_diff[&Address::from_low_u64_be(1)].clone();
+        assert_eq!(
+            sender.balance,
+            Diff::Changed(ChangedType {
+                from: 0x10.into(),
+                to: 0x8.into(),
+            })
+        );
+        assert_eq!(
+            sender.nonce,
+            Diff::Changed(ChangedType {
+                from: 1.into(),
+                to: 2.into(),
+            })
+        );
+        assert_eq!(
+            state_diff[&Address::from_low_u64_be(2)].storage[&H256::zero()],
+            Diff::Changed(ChangedType {
+                from: H256::from_low_u64_be(1),
+                to: H256::zero(),
+            })
+        );
+    }
+}
@@ -0,0 +1,23 @@
+pub mod evm;
+pub mod geth;
+pub mod parity;
+
+use crate::utils::{ArbitrageError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use std::sync::Arc;
+
+// Simulate the tx and return the trace in parity `BlockTrace` shape (call tree + state diff),
+// so the analysis doesn't care about which backend is used.
+#[async_trait]
+pub trait SimulateBackend<M, S>: Send + Sync {
+    async fn init(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError>
+    where
+        Self: Sized;
+
+    async fn trace(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, ArbitrageError>;
+}
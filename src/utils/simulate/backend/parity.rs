@@ -0,0 +1,30 @@
+use super::SimulateBackend;
+use crate::utils::{ArbitrageError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use std::sync::Arc;
+
+// Only parity node support `trace_call`, recommend `ankr` rpc. (Sometimes it fails, need to retry)
+pub struct ParityBackend<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static, S: Signer + 'static> SimulateBackend<M, S> for ParityBackend<M, S> {
+    async fn init(client: Arc<SignerMiddleware<M, S>>) -> Result<Self, ArbitrageError> {
+        Ok(Self { client })
+    }
+
+    async fn trace(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, ArbitrageError> {
+        let trace = self
+            .client
+            .trace_call(tx, vec![TraceType::Trace, TraceType::StateDiff], block)
+            .await?;
+
+        Ok(trace.into())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::utils::ArbitrageError;
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallTreeError {
+    // The backend returns no call trace at all.
+    Empty,
+    MissingRoot,
+    DuplicateCall(Vec<usize>),
+    // The call is recorded but its parent isn't.
+    MissingParent(Vec<usize>),
+    // The parent declares more subtraces than recorded, e.g. the trace is cut off.
+    MissingCall(Vec<usize>),
+    // The call is beyond the subtraces declared by the parent.
+    UnexpectedCall(Vec<usize>),
+}
+
+impl fmt::Display for CallTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty call trace"),
+            Self::MissingRoot => write!(f, "Missing the origin call in the trace"),
+            Self::DuplicateCall(address) => write!(f, "Duplicate call {address:?} in the trace"),
+            Self::MissingParent(address) => {
+                write!(f, "Missing the parent of call {address:?} in the trace")
+            }
+            Self::MissingCall(address) => write!(f, "Missing call {address:?} in the trace"),
+            Self::UnexpectedCall(address) => {
+                write!(f, "Call {address:?} exceeds the subtraces of its parent")
+            }
+        }
+    }
+}
+
+impl Error for CallTreeError {}
+
+impl From<CallTreeError> for ArbitrageError {
+    fn from(error: CallTreeError) -> Self {
+        Self::Trace(Box::new(error))
+    }
+}
+
+// The call tree of the traced tx, keyed by `trace_address`, the child index path from the origin call.
+// The path order is the execution order, e.g. [] < [0] < [0, 0] < [1].
+#[derive(Debug, Clone)]
+pub struct CallTree<'t> {
+    call_map: BTreeMap<&'t [usize], &'t TransactionTrace>,
+}
+
+impl<'t> CallTree<'t> {
+    // Every call must be reachable from the origin call and match the subtraces of its parent.
+    pub fn build(trace_list: &'t [TransactionTrace]) -> Result<Self, CallTreeError> {
+        if trace_list.is_empty() {
+            return Err(CallTreeError::Empty);
+        }
+
+        let mut call_map = BTreeMap::new();
+        for trace in trace_list {
+            if call_map
+                .insert(trace.trace_address.as_slice(), trace)
+                .is_some()
+            {
+                return Err(CallTreeError::DuplicateCall(trace.trace_address.clone()));
+            }
+        }
+        if !call_map.contains_key([].as_slice()) {
+            return Err(CallTreeError::MissingRoot);
+        }
+        for (address, trace) in &call_map {
+            if let Some((index, parent)) = address.split_last() {
+                match call_map.get(parent) {
+                    None => return Err(CallTreeError::MissingParent(address.to_vec())),
+                    Some(parent) if *index >= parent.subtraces => {
+                        return Err(CallTreeError::UnexpectedCall(address.to_vec()))
+                    }
+                    _ => {}
+                }
+            }
+            // The indexes are below `subtraces` and unique, so the last one is enough.
+            if trace.subtraces > 0 {
+                let mut last = address.to_vec();
+                last.push(trace.subtraces - 1);
+                if !call_map.contains_key(last.as_slice()) {
+                    return Err(CallTreeError::MissingCall(last));
+                }
+            }
+        }
+
+        Ok(Self { call_map })
+    }
+
+    pub fn root(&self) -> &'t TransactionTrace {
+        self.call_map[[].as_slice()]
+    }
+
+    // The direct subcalls of the call in order, empty if the call isn't in the tree.
+    pub fn children(&self, address: &[usize]) -> Vec<&'t TransactionTrace> {
+        let subtraces = self
+            .call_map
+            .get(address)
+            .map_or(0, |trace| trace.subtraces);
+        (0..subtraces)
+            .filter_map(|index| {
+                let mut child = address.to_vec();
+                child.push(index);
+                self.call_map.get(child.as_slice()).copied()
+            })
+            .collect()
+    }
+
+    // The calls at `depth` in execution order, 0 for the origin call, 1 for its subcalls and so on.
+    pub fn at_depth(&self, depth: usize) -> Vec<&'t TransactionTrace> {
+        self.call_map
+            .iter()
+            .filter(|(address, _)| address.len() == depth)
+            .map(|(_, trace)| *trace)
+            .collect()
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.call_map
+            .keys()
+            .map(|address| address.len())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallTree, CallTreeError};
+    use ethers::prelude::*;
+
+    fn to_trace(trace_address: &[usize], subtraces: usize) -> TransactionTrace {
+        TransactionTrace {
+            trace_address: trace_address.to_vec(),
+            subtraces,
+            action: Action::Call(Call {
+                // Tell the calls apart by the value.
+                value: U256::from(trace_address.iter().fold(1, |key, i| key * 10 + i + 1)),
+                ..Default::default()
+            }),
+            action_type: ActionType::Call,
+            result: None,
+            error: None,
+        }
+    }
+
+    fn to_value(trace: &TransactionTrace) -> u64 {
+        match &trace.action {
+            Action::Call(call) => call.value.as_u64(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn build_call_tree() {
+        // [1] and [0, 0] collide with the key of `v * 2^i + 1`.
+        let trace_list = vec![
+            to_trace(&[1], 0),
+            to_trace(&[], 2),
+            to_trace(&[0, 0], 0),
+            to_trace(&[0], 1),
+        ];
+        let tree = CallTree::build(&trace_list).unwrap();
+
+        assert_eq!(to_value(tree.root()), 1);
+        assert_eq!(
+            tree.at_depth(1)
+                .into_iter()
+                .map(to_value)
+                .collect::<Vec<_>>(),
+            vec![11, 12]
+        );
+        assert_eq!(
+            tree.at_depth(2)
+                .into_iter()
+                .map(to_value)
+                .collect::<Vec<_>>(),
+            vec![111]
+        );
+        assert!(tree.at_depth(3).is_empty());
+        assert_eq!(tree.children(&[0]).len(), 1);
+        assert!(tree.children(&[5]).is_empty());
+        assert_eq!(tree.max_depth(), 2);
+    }
+
+    #[tokio::test]
+    async fn reject_malformed_trace() {
+        assert_eq!(CallTree::build(&[]).unwrap_err(), CallTreeError::Empty);
+        assert_eq!(
+            CallTree::build(&[to_trace(&[0], 0)]).unwrap_err(),
+            CallTreeError::MissingRoot
+        );
+        assert_eq!(
+            CallTree::build(&[to_trace(&[], 1), to_trace(&[0], 0), to_trace(&[0], 0)]).unwrap_err(),
+            CallTreeError::DuplicateCall(vec![0])
+        );
+        assert_eq!(
+            CallTree::build(&[to_trace(&[], 0), to_trace(&[0, 0], 0)]).unwrap_err(),
+            CallTreeError::MissingParent(vec![0, 0])
+        );
+        // The trace is cut off after the first subcall.
+        assert_eq!(
+            CallTree::build(&[to_trace(&[], 2), to_trace(&[0], 0)]).unwrap_err(),
+            CallTreeError::MissingCall(vec![1])
+        );
+        assert_eq!(
+            CallTree::build(&[to_trace(&[], 1), to_trace(&[0], 0), to_trace(&[1], 0)]).unwrap_err(),
+            CallTreeError::UnexpectedCall(vec![1])
+        );
+    }
+}
@@ -1,19 +1,28 @@
-use crate::utils::SimulateTrace;
+use crate::utils::{ArbitrageError, PriceOracle, SimulateTrace};
 use async_trait::async_trait;
 use ethers::prelude::*;
-use std::error::Error;
+use std::sync::Arc;
 
 #[async_trait]
-pub trait AnalyzeState<'a, M, S> {
-    async fn init(client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>>
+pub trait AnalyzeState<M, S>: Send + Sync {
+    // The oracle is shared by all analysis to convert the profit to native token.
+    async fn init(
+        client: Arc<SignerMiddleware<M, S>>,
+        oracle: Arc<PriceOracle<M, S>>,
+    ) -> Result<Self, ArbitrageError>
     where
         Self: Sized;
 
+    // Unique name to enable the analysis by config.
+    fn name(&self) -> &str;
+
+    // @param block The number of the simulated block, e.g. to price the token at
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>>;
+        block: U64,
+    ) -> Result<Option<U256>, ArbitrageError>;
 }
 
 #[derive(Default, Debug)]
@@ -1,23 +1,31 @@
 use super::base::{AnalyzeState, DiffAnalysis};
-use crate::utils::SimulateTrace;
+use crate::utils::{ArbitrageError, PriceOracle, SimulateTrace};
 use async_trait::async_trait;
 use ethers::prelude::*;
-use std::error::Error;
+use std::sync::Arc;
 
 // Analyze whether the native token is profitable.
 pub struct AnalyzeEth;
 
 #[async_trait]
-impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeEth {
-    async fn init(_client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
+impl<M: Middleware + 'static, S: Signer + 'static> AnalyzeState<M, S> for AnalyzeEth {
+    async fn init(
+        _client: Arc<SignerMiddleware<M, S>>,
+        _oracle: Arc<PriceOracle<M, S>>,
+    ) -> Result<Self, ArbitrageError> {
         Ok(Self)
     }
 
+    fn name(&self) -> &str {
+        "eth"
+    }
+
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>> {
+        _block: U64,
+    ) -> Result<Option<U256>, ArbitrageError> {
         let mut profit = U256::zero();
 
         if let Some(state_diff) = &trace.state_diff {
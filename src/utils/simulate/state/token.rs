@@ -1,24 +1,469 @@
 use super::base::AnalyzeState;
-use crate::utils::SimulateTrace;
+use crate::utils::{ArbitrageError, PriceOracle, SimulateTrace};
 use async_trait::async_trait;
-use ethers::prelude::*;
-use std::error::Error;
+use ethers::{
+    abi::{self, ParamType},
+    prelude::*,
+    utils::{id, keccak256},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+// Balance mapping is usually declared in the first few slots of the token contract.
+const BALANCE_SLOT_RANGE: u64 = 20;
+
+// The amount of `token` gained by `holder` in the simulated tx.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenGain {
+    pub token: Address,
+    pub holder: Address,
+    pub amount: U256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Transfer {
+    token: Address,
+    from: Address,
+    to: Address,
+    amount: U256,
+}
 
 // @dev Analyze whether the contract token (erc20, erc223, erc777, etc.) is profitable
 // @return The profit convert to native token
-pub struct AnalyzeToken;
+pub struct AnalyzeToken<M, S> {
+    oracle: Arc<PriceOracle<M, S>>,
+}
 
 #[async_trait]
-impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeToken {
-    async fn init(client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
-        Ok(Self)
+impl<M: Middleware + 'static, S: Signer + 'static> AnalyzeState<M, S> for AnalyzeToken<M, S> {
+    async fn init(
+        _client: Arc<SignerMiddleware<M, S>>,
+        oracle: Arc<PriceOracle<M, S>>,
+    ) -> Result<Self, ArbitrageError> {
+        Ok(Self { oracle })
+    }
+
+    fn name(&self) -> &str {
+        "token"
     }
 
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>> {
-        Ok(None)
+        block: U64,
+    ) -> Result<Option<U256>, ArbitrageError> {
+        // The token without pool to price is ignored, so is the one failing to be priced,
+        // e.g. the pool can't be loaded or simulated, the other tokens are still counted.
+        let mut profit = U256::zero();
+        for gain in gains(tx, trace) {
+            if let Ok(Some(value)) = self.oracle.to_native(gain.token, gain.amount, block).await {
+                profit += value;
+            }
+        }
+
+        if profit.is_zero() {
+            Ok(None)
+        } else {
+            Ok(Some(profit))
+        }
+    }
+}
+
+// Token gains of the sender and `tx.to`.
+// The balance slot change in state diff is preferred, because it is the final balance
+// (fee-on-transfer, rebase, mint without event, etc.), then fall back to the transfers.
+pub fn gains(tx: &Transaction, trace: &SimulateTrace) -> Vec<TokenGain> {
+    let holders = holders(tx);
+    let balance_changes = balance_changes(&holders, trace);
+    let transfers = transfers(trace);
+
+    let mut gains = Vec::new();
+    for holder in holders {
+        let tokens = balance_changes
+            .keys()
+            .filter(|(_, h)| *h == holder)
+            .map(|(token, _)| *token)
+            .chain(
+                transfers
+                    .iter()
+                    .filter(|t| t.from == holder || t.to == holder)
+                    .map(|t| t.token),
+            )
+            .collect::<BTreeSet<_>>();
+
+        for token in tokens {
+            let amount = match balance_changes.get(&(token, holder)) {
+                Some((before, after)) => after.saturating_sub(*before),
+                None => {
+                    let (income, expense) = transfers.iter().filter(|t| t.token == token).fold(
+                        (U256::zero(), U256::zero()),
+                        |(income, expense), t| match (t.to == holder, t.from == holder) {
+                            (true, false) => (income + t.amount, expense),
+                            (false, true) => (income, expense + t.amount),
+                            _ => (income, expense),
+                        },
+                    );
+                    income.saturating_sub(expense)
+                }
+            };
+
+            if !amount.is_zero() {
+                gains.push(TokenGain {
+                    token,
+                    holder,
+                    amount,
+                });
+            }
+        }
+    }
+
+    gains
+}
+
+// The max drawdown of the token balance of the sender and `tx.to` in the transfer order,
+// that is the capital required upfront to replay the tx. The native token value isn't counted.
+pub fn required_capital(tx: &Transaction, trace: &SimulateTrace) -> Vec<TokenGain> {
+    let holders = holders(tx);
+
+    // (token, holder) => (balance, drawdown)
+    let mut balances: BTreeMap<(Address, Address), (U256, U256)> = BTreeMap::new();
+    for transfer in transfers(trace) {
+        if transfer.from == transfer.to {
+            continue;
+        }
+        if holders.contains(&transfer.to) {
+            let (balance, _) = balances.entry((transfer.token, transfer.to)).or_default();
+            *balance += transfer.amount;
+        }
+        if holders.contains(&transfer.from) {
+            let (balance, drawdown) = balances.entry((transfer.token, transfer.from)).or_default();
+            if *balance >= transfer.amount {
+                *balance -= transfer.amount;
+            } else {
+                *drawdown += transfer.amount - *balance;
+                *balance = U256::zero();
+            }
+        }
+    }
+
+    balances
+        .into_iter()
+        .filter(|(_, (_, drawdown))| !drawdown.is_zero())
+        .map(|((token, holder), (_, amount))| TokenGain {
+            token,
+            holder,
+            amount,
+        })
+        .collect()
+}
+
+fn holders(tx: &Transaction) -> Vec<Address> {
+    let mut holders = vec![tx.from];
+    if let Some(to) = tx.to {
+        if to != tx.from {
+            holders.push(to);
+        }
+    }
+    holders
+}
+
+// Match the changed storage slots with the balance slot of holders, both solidity
+// `keccak256(holder . slot)` and vyper `keccak256(slot . holder)` layouts are supported.
+// @return (token, holder) => (balance before, balance after)
+fn balance_changes(
+    holders: &[Address],
+    trace: &SimulateTrace,
+) -> HashMap<(Address, Address), (U256, U256)> {
+    let mut slot_map = HashMap::new();
+    for holder in holders {
+        for slot in 0..BALANCE_SLOT_RANGE {
+            let holder_token = abi::Token::Address(*holder);
+            let slot_token = abi::Token::Uint(slot.into());
+            slot_map.insert(
+                H256::from(keccak256(abi::encode(&[
+                    holder_token.clone(),
+                    slot_token.clone(),
+                ]))),
+                *holder,
+            );
+            slot_map.insert(
+                H256::from(keccak256(abi::encode(&[slot_token, holder_token]))),
+                *holder,
+            );
+        }
+    }
+
+    let mut changes = HashMap::new();
+    if let Some(state_diff) = &trace.state_diff {
+        for (token, account_diff) in &state_diff.0 {
+            for (slot, diff) in &account_diff.storage {
+                if let Some(holder) = slot_map.get(slot) {
+                    let change = match diff {
+                        Diff::Changed(ChangedType { from, to }) => (*from, *to),
+                        Diff::Born(to) => (H256::zero(), *to),
+                        Diff::Died(from) => (*from, H256::zero()),
+                        Diff::Same => continue,
+                    };
+                    changes.insert(
+                        (*token, *holder),
+                        (
+                            U256::from_big_endian(change.0.as_bytes()),
+                            U256::from_big_endian(change.1.as_bytes()),
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+// Decode `Transfer(address,address,uint256)` logs, if the backend doesn't return logs,
+// decode the successful `transfer` / `transferFrom` calls in the trace instead.
+fn transfers(trace: &SimulateTrace) -> Vec<Transfer> {
+    if !trace.logs.is_empty() {
+        return trace.logs.iter().filter_map(decode_transfer_log).collect();
+    }
+
+    let mut reverted: Vec<&[usize]> = Vec::new();
+    let mut transfers = Vec::new();
+    for call_trace in trace.trace.iter().flatten() {
+        // The sub call of a reverted call is reverted too.
+        if call_trace.error.is_some() {
+            reverted.push(&call_trace.trace_address);
+            continue;
+        }
+        if reverted
+            .iter()
+            .any(|address| call_trace.trace_address.starts_with(address))
+        {
+            continue;
+        }
+        if let Action::Call(call) = &call_trace.action {
+            if let Some(transfer) = decode_transfer_call(call) {
+                transfers.push(transfer);
+            }
+        }
+    }
+
+    transfers
+}
+
+fn decode_transfer_log(log: &Log) -> Option<Transfer> {
+    let topic = H256::from(keccak256("Transfer(address,address,uint256)"));
+    // erc721 `Transfer` has an indexed token id, so the data is empty.
+    if log.removed == Some(true)
+        || log.topics.len() != 3
+        || log.topics[0] != topic
+        || log.data.len() != 32
+    {
+        return None;
+    }
+
+    Some(Transfer {
+        token: log.address,
+        from: Address::from(log.topics[1]),
+        to: Address::from(log.topics[2]),
+        amount: U256::from_big_endian(&log.data),
+    })
+}
+
+fn decode_transfer_call(call: &Call) -> Option<Transfer> {
+    if call.call_type != CallType::Call || call.input.len() < 4 {
+        return None;
+    }
+
+    let (selector, data) = call.input.split_at(4);
+    if selector == id("transfer(address,uint256)") {
+        match abi::decode(&[ParamType::Address, ParamType::Uint(256)], data).ok()?[..] {
+            [abi::Token::Address(to), abi::Token::Uint(amount)] => Some(Transfer {
+                token: call.to,
+                from: call.from,
+                to,
+                amount,
+            }),
+            _ => None,
+        }
+    } else if selector == id("transferFrom(address,address,uint256)") {
+        match abi::decode(
+            &[ParamType::Address, ParamType::Address, ParamType::Uint(256)],
+            data,
+        )
+        .ok()?[..]
+        {
+            [abi::Token::Address(from), abi::Token::Address(to), abi::Token::Uint(amount)] => {
+                Some(Transfer {
+                    token: call.to,
+                    from,
+                    to,
+                    amount,
+                })
+            }
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gains, required_capital, TokenGain};
+    use crate::utils::SimulateTrace;
+    use ethers::{
+        abi,
+        prelude::*,
+        utils::{id, keccak256},
+    };
+    use std::collections::BTreeMap;
+
+    fn to_trace(
+        trace: Vec<TransactionTrace>,
+        state_diff: BTreeMap<H160, AccountDiff>,
+    ) -> SimulateTrace {
+        BlockTrace {
+            output: Bytes::default(),
+            trace: Some(trace),
+            vm_trace: None,
+            state_diff: Some(StateDiff(state_diff)),
+            transaction_hash: None,
+        }
+        .into()
+    }
+
+    fn to_call_trace(
+        trace_address: Vec<usize>,
+        call: Call,
+        error: Option<String>,
+    ) -> TransactionTrace {
+        TransactionTrace {
+            trace_address,
+            subtraces: 0,
+            action: Action::Call(call),
+            action_type: ActionType::Call,
+            result: None,
+            error,
+        }
+    }
+
+    fn to_tx(from: Address, to: Address) -> Transaction {
+        Transaction {
+            from,
+            to: Some(to),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn gains_from_balance_slot() {
+        let tx = to_tx(Address::random(), Address::random());
+        let token = Address::random();
+        let slot = H256::from(keccak256(abi::encode(&[
+            abi::Token::Address(tx.from),
+            abi::Token::Uint(3.into()),
+        ])));
+
+        let mut storage = BTreeMap::new();
+        storage.insert(
+            slot,
+            Diff::Changed(ChangedType {
+                from: H256::from_low_u64_be(100),
+                to: H256::from_low_u64_be(250),
+            }),
+        );
+        let mut state_diff = BTreeMap::new();
+        state_diff.insert(
+            token,
+            AccountDiff {
+                balance: Diff::Same,
+                nonce: Diff::Same,
+                code: Diff::Same,
+                storage,
+            },
+        );
+
+        assert_eq!(
+            gains(&tx, &to_trace(vec![], state_diff)),
+            vec![TokenGain {
+                token,
+                holder: tx.from,
+                amount: 150.into(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn gains_from_transfer_call() {
+        let tx = to_tx(Address::random(), Address::random());
+        let token = Address::random();
+        let transfer = |to: Address, amount: u64| {
+            let mut input = id("transfer(address,uint256)").to_vec();
+            input.extend(abi::encode(&[
+                abi::Token::Address(to),
+                abi::Token::Uint(amount.into()),
+            ]));
+            Call {
+                from: Address::random(),
+                to: token,
+                input: input.into(),
+                call_type: CallType::Call,
+                ..Default::default()
+            }
+        };
+
+        let trace = vec![
+            to_call_trace(vec![], transfer(tx.to.unwrap(), 10), None),
+            to_call_trace(
+                vec![0],
+                transfer(tx.to.unwrap(), 20),
+                Some("Reverted".into()),
+            ),
+            // Reverted with the parent call.
+            to_call_trace(vec![0, 0], transfer(tx.to.unwrap(), 30), None),
+        ];
+
+        assert_eq!(
+            gains(&tx, &to_trace(trace, BTreeMap::new())),
+            vec![TokenGain {
+                token,
+                holder: tx.to.unwrap(),
+                amount: 10.into(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn required_capital_from_transfer_log() {
+        let tx = to_tx(Address::random(), Address::random());
+        let token = Address::random();
+        let other = Address::random();
+        let transfer = |from: Address, to: Address, amount: u64| Log {
+            address: token,
+            topics: vec![
+                H256::from(keccak256("Transfer(address,address,uint256)")),
+                H256::from(from),
+                H256::from(to),
+            ],
+            data: abi::encode(&[abi::Token::Uint(amount.into())]).into(),
+            ..Default::default()
+        };
+
+        let mut trace = to_trace(vec![], BTreeMap::new());
+        trace.logs = vec![
+            transfer(tx.from, other, 30),
+            transfer(other, tx.from, 50),
+            transfer(tx.from, other, 40),
+        ];
+
+        assert_eq!(
+            required_capital(&tx, &trace),
+            vec![TokenGain {
+                token,
+                holder: tx.from,
+                amount: 30.into(),
+            }]
+        );
     }
 }
@@ -0,0 +1,191 @@
+use async_trait::async_trait;
+use ethers::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// Prefilter the tx before the simulation, prune as much as possible.
+#[async_trait]
+pub trait Strategy: Send + Sync {
+    // Unique name, used to order and switch the strategy.
+    fn name(&self) -> &str;
+
+    // @return The reason if the tx is rejected
+    async fn run(&self, tx: &Transaction) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub strategy: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rejected by strategy <{}>: {}",
+            self.strategy, self.reason
+        )
+    }
+}
+
+impl Error for Rejection {}
+
+#[derive(Clone)]
+struct StrategyEntry {
+    strategy: Arc<dyn Strategy>,
+    enabled: bool,
+}
+
+// Run the enabled strategies in order, stop at the first rejection.
+// The clone shares the strategies, registering or switching one doesn't affect the other.
+#[derive(Default, Clone)]
+pub struct StrategyList {
+    list: Vec<StrategyEntry>,
+}
+
+impl StrategyList {
+    // Append the strategy, the one with the same name is replaced in place.
+    pub fn register(&mut self, strategy: impl Strategy + 'static) -> &mut Self {
+        let entry = StrategyEntry {
+            strategy: Arc::new(strategy),
+            enabled: true,
+        };
+        match self.position(entry.strategy.name()) {
+            Some(index) => self.list[index] = entry,
+            None => self.list.push(entry),
+        }
+        self
+    }
+
+    // Insert the strategy at `index`, the one with the same name is removed first.
+    pub fn register_at(&mut self, index: usize, strategy: impl Strategy + 'static) -> &mut Self {
+        self.remove(strategy.name());
+        self.list.insert(
+            index.min(self.list.len()),
+            StrategyEntry {
+                strategy: Arc::new(strategy),
+                enabled: true,
+            },
+        );
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.position(name) {
+            Some(index) => {
+                self.list.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // @return False if the strategy isn't registered
+    pub fn enable(&mut self, name: &str, enabled: bool) -> bool {
+        match self.position(name) {
+            Some(index) => {
+                self.list[index].enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Names in the running order.
+    pub fn names(&self) -> Vec<&str> {
+        self.list
+            .iter()
+            .map(|entry| entry.strategy.name())
+            .collect()
+    }
+
+    pub async fn run(&self, tx: &Transaction) -> Result<(), Rejection> {
+        for entry in self.list.iter().filter(|entry| entry.enabled) {
+            if let Err(reason) = entry.strategy.run(tx).await {
+                return Err(Rejection {
+                    strategy: entry.strategy.name().into(),
+                    reason,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|entry| entry.strategy.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rejection, Strategy, StrategyList};
+    use async_trait::async_trait;
+    use ethers::prelude::*;
+
+    struct GasPriceFloor(U256);
+
+    #[async_trait]
+    impl Strategy for GasPriceFloor {
+        fn name(&self) -> &str {
+            "gas_price"
+        }
+
+        async fn run(&self, tx: &Transaction) -> Result<(), String> {
+            match tx.gas_price {
+                Some(gas_price) if gas_price >= self.0 => Ok(()),
+                _ => Err("Gas price too low".into()),
+            }
+        }
+    }
+
+    struct Reject(&'static str);
+
+    #[async_trait]
+    impl Strategy for Reject {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn run(&self, _tx: &Transaction) -> Result<(), String> {
+            Err(format!("Rejected by {}", self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn run_strategy_in_order() {
+        let tx = Transaction {
+            gas_price: Some(1.into()),
+            ..Default::default()
+        };
+        let mut strategy_list = StrategyList::default();
+        strategy_list
+            .register(Reject("a"))
+            .register(GasPriceFloor(10.into()))
+            .register_at(0, Reject("b"));
+        assert_eq!(strategy_list.names(), vec!["b", "a", "gas_price"]);
+
+        assert_eq!(
+            strategy_list.run(&tx).await,
+            Err(Rejection {
+                strategy: "b".into(),
+                reason: "Rejected by b".into(),
+            })
+        );
+
+        strategy_list.enable("b", false);
+        strategy_list.remove("a");
+        assert_eq!(
+            strategy_list.run(&tx).await.unwrap_err().reason,
+            "Gas price too low"
+        );
+
+        strategy_list.register(GasPriceFloor(1.into()));
+        assert_eq!(strategy_list.names(), vec!["b", "gas_price"]);
+        assert!(strategy_list.run(&tx).await.is_ok());
+    }
+}
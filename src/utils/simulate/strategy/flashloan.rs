@@ -1,7 +1,171 @@
+use super::super::state::token::TokenGain;
+use crate::utils::{encode_multicall, IERC3156FlashLender};
 use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-// The `flashloan` function simulate basically fails (callback interface / calldata format).
-// What we expect to simulate is subtrace, so you have to prepare funds yourself firstly.
-pub fn run(_tx: &Transaction) -> bool {
-    true
+// Borrow the token that the replay pays before being paid back from an ERC-3156 lender,
+// so there is no need to prepare funds in the contract.
+// `Arbitrage.onFlashLoan` only checks the native token balance and can't pay the fee in token,
+// so only the lender without fee is used.
+pub struct Flashloan<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+    lender_list: Vec<Address>,
+}
+
+impl<M, S> Clone for Flashloan<M, S> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            lender_list: self.lender_list.clone(),
+        }
+    }
+}
+
+impl<M: Middleware + 'static, S: Signer + 'static> Flashloan<M, S> {
+    pub fn init(client: Arc<SignerMiddleware<M, S>>, lender_list: Vec<Address>) -> Self {
+        Self {
+            client,
+            lender_list,
+        }
+    }
+
+    pub fn set_lender_list(&mut self, lender_list: Vec<Address>) {
+        self.lender_list = lender_list;
+    }
+
+    // Wrap the tx list in the `flashLoan` call of the required token.
+    // Only one token is borrowed, the contract only trusts one lender and the nested loan of the same lender
+    // reenters it, e.g. the Balancer vault is `nonReentrant`, and fails silently in the multicall.
+    // @return The origin tx list if more than one token is required or no lender can lend it,
+    // the contract may have prepared the funds.
+    pub async fn wrap(
+        &self,
+        receiver: Address,
+        tx_list: Vec<TransactionRequest>,
+        required: &[TokenGain],
+    ) -> Vec<TransactionRequest> {
+        let mut amounts = BTreeMap::new();
+        for capital in required {
+            *amounts.entry(capital.token).or_insert_with(U256::zero) += capital.amount;
+        }
+        if amounts.len() != 1 {
+            return tx_list;
+        }
+        let (token, amount) = amounts.into_iter().next().unwrap();
+        let lender = match self.find_lender(token, amount).await {
+            Some(lender) => lender,
+            None => return tx_list,
+        };
+
+        // The callback data is the same as `Arbitrage.run`, without uncle protection and coinbase fee.
+        let call = IERC3156FlashLender::new(lender, self.client.clone()).flash_loan(
+            receiver,
+            token,
+            amount,
+            encode_multicall(tx_list, H256::zero(), U256::zero()),
+        );
+        vec![TransactionRequest::new()
+            .from(self.client.signer().address())
+            .to(lender)
+            .value(U256::zero())
+            .data(call.calldata().unwrap_or_default())]
+    }
+
+    async fn find_lender(&self, token: Address, amount: U256) -> Option<Address> {
+        for lender in &self.lender_list {
+            let lender_contract = IERC3156FlashLender::new(*lender, self.client.clone());
+            // The lender doesn't support the token reverts, regard it as unavailable.
+            let max_amount = lender_contract.max_flash_loan(token).call().await;
+            let fee = lender_contract.flash_fee(token, amount).call().await;
+            if matches!((max_amount, fee), (Ok(max_amount), Ok(fee)) if max_amount >= amount && fee.is_zero())
+            {
+                return Some(*lender);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::state::token::TokenGain;
+    use super::Flashloan;
+    use crate::utils::FlashLoanCall;
+    use ethers::{abi::AbiDecode, core::rand::thread_rng, prelude::*, utils::parse_ether};
+    use std::sync::Arc;
+
+    fn to_uint(value: U256) -> Bytes {
+        abi::encode(&[abi::Token::Uint(value)]).into()
+    }
+
+    #[tokio::test]
+    async fn borrow_non_dai_token() {
+        let (provider, mock) = Provider::mocked();
+        let client = Arc::new(SignerMiddleware::new(
+            provider,
+            LocalWallet::new(&mut thread_rng()),
+        ));
+        let (dai_lender, multi_asset_lender) = (Address::random(), Address::random());
+        let flashloan = Flashloan::init(client, vec![dai_lender, multi_asset_lender]);
+        let (receiver, weth) = (Address::random(), Address::random());
+        let required = vec![TokenGain {
+            token: weth,
+            holder: Address::random(),
+            amount: parse_ether(10).unwrap(),
+        }];
+
+        // `maxFlashLoan` and `flashFee` of each lender in order, the responses are popped from the back.
+        mock.push::<Bytes, _>(to_uint(U256::zero())).unwrap();
+        mock.push::<Bytes, _>(to_uint(parse_ether(1000).unwrap()))
+            .unwrap();
+        // DssFlash lends nothing but DAI and reverts the fee of the others.
+        mock.push::<Bytes, _>(Bytes::default()).unwrap();
+        mock.push::<Bytes, _>(to_uint(U256::zero())).unwrap();
+
+        let tx_list = flashloan.wrap(receiver, Vec::new(), &required).await;
+        assert_eq!(tx_list.len(), 1);
+        assert_eq!(tx_list[0].to, Some(multi_asset_lender.into()));
+        let call = FlashLoanCall::decode(tx_list[0].data.as_ref().unwrap()).unwrap();
+        assert_eq!(call.receiver, receiver);
+        assert_eq!(call.token, weth);
+        assert_eq!(call.amount, parse_ether(10).unwrap());
+
+        // No lender can lend it, the contract may have prepared the funds.
+        mock.push::<Bytes, _>(to_uint(U256::zero())).unwrap();
+        mock.push::<Bytes, _>(to_uint(parse_ether(1).unwrap()))
+            .unwrap();
+        mock.push::<Bytes, _>(Bytes::default()).unwrap();
+        mock.push::<Bytes, _>(to_uint(U256::zero())).unwrap();
+        assert!(flashloan
+            .wrap(receiver, Vec::new(), &required)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn not_borrow_multiple_tokens() {
+        let (provider, mock) = Provider::mocked();
+        let client = Arc::new(SignerMiddleware::new(
+            provider,
+            LocalWallet::new(&mut thread_rng()),
+        ));
+        let flashloan = Flashloan::init(client, vec![Address::random()]);
+        let required = [Address::random(), Address::random()].map(|token| TokenGain {
+            token,
+            holder: Address::random(),
+            amount: parse_ether(10).unwrap(),
+        });
+        let tx_list = vec![TransactionRequest::new().to(Address::random())];
+
+        // The origin tx list without asking any lender.
+        assert_eq!(
+            flashloan
+                .wrap(Address::random(), tx_list.clone(), &required)
+                .await,
+            tx_list
+        );
+        assert!(mock.assert_request("eth_call", ()).is_err());
+    }
 }
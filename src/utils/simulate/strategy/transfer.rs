@@ -1,3 +1,5 @@
+use super::base::Strategy;
+use async_trait::async_trait;
 use ethers::prelude::*;
 
 // Only filter native token transfer tx.
@@ -6,6 +8,23 @@ pub fn run(tx: &Transaction) -> bool {
     !tx.input.is_empty()
 }
 
+pub struct TransferStrategy;
+
+#[async_trait]
+impl Strategy for TransferStrategy {
+    fn name(&self) -> &str {
+        "transfer"
+    }
+
+    async fn run(&self, tx: &Transaction) -> Result<(), String> {
+        if run(tx) {
+            Ok(())
+        } else {
+            Err("Native token transfer".into())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::run as pass_transfer_check;
@@ -0,0 +1,214 @@
+use ethers::prelude::*;
+use ethers::utils::to_checksum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // Left padded to a 32-byte ABI word, also the words of the calldata nested in `bytes`.
+    Word,
+    // The raw 20 bytes, e.g. `abi.encodePacked` or the uniswap v3 path.
+    Packed,
+    // The hex text in a string, lowercase or checksummed.
+    Hex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    // The byte offset in the data.
+    pub offset: usize,
+    pub from: Address,
+    pub to: Address,
+    pub encoding: Encoding,
+}
+
+// Replace the addresses in the calldata, e.g. the sender and the recipient of the victim tx with the contract.
+// The match is on the whole 20 bytes, so there is no false positive on the unaligned hex digits.
+#[derive(Debug, Clone, Default)]
+pub struct AddressSubstitute {
+    // (from, to) in priority order
+    pair_list: Vec<(Address, Address)>,
+}
+
+impl AddressSubstitute {
+    // The zero address and the one already added are ignored.
+    pub fn with(mut self, from: Address, to: Address) -> Self {
+        if !from.is_zero() && from != to && !self.pair_list.iter().any(|(f, _)| *f == from) {
+            self.pair_list.push((from, to));
+        }
+        self
+    }
+
+    // Keep the address, e.g. the callee itself.
+    pub fn except(mut self, address: Address) -> Self {
+        self.pair_list.retain(|(from, _)| *from != address);
+        self
+    }
+
+    // @return The substituted data and every substitution in order
+    pub fn run(&self, data: &[u8]) -> (Bytes, Vec<Substitution>) {
+        let pattern_list = self
+            .pair_list
+            .iter()
+            .map(|(from, to)| Pattern {
+                from: *from,
+                to: *to,
+                lower_hex: to_lower_hex(from),
+                checksum_hex: to_checksum_hex(from),
+            })
+            .collect::<Vec<_>>();
+
+        let mut output = data.to_vec();
+        let mut substitution_list = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let (pattern, encoding) = match find(&pattern_list, data, offset) {
+                Some(found) => found,
+                None => {
+                    offset += 1;
+                    continue;
+                }
+            };
+            let replacement = match encoding {
+                Encoding::Word | Encoding::Packed => pattern.to.as_bytes().to_vec(),
+                // Keep the case of the text.
+                Encoding::Hex if data[offset..].starts_with(&pattern.lower_hex) => {
+                    to_lower_hex(&pattern.to)
+                }
+                Encoding::Hex => to_checksum_hex(&pattern.to),
+            };
+            output[offset..offset + replacement.len()].copy_from_slice(&replacement);
+            substitution_list.push(Substitution {
+                offset,
+                from: pattern.from,
+                to: pattern.to,
+                encoding,
+            });
+            offset += replacement.len();
+        }
+
+        (output.into(), substitution_list)
+    }
+}
+
+struct Pattern {
+    from: Address,
+    to: Address,
+    lower_hex: Vec<u8>,
+    checksum_hex: Vec<u8>,
+}
+
+fn find<'p>(
+    pattern_list: &'p [Pattern],
+    data: &[u8],
+    offset: usize,
+) -> Option<(&'p Pattern, Encoding)> {
+    let rest = &data[offset..];
+    pattern_list.iter().find_map(|pattern| {
+        if rest.starts_with(pattern.from.as_bytes()) {
+            let padded = offset >= 12 && data[offset - 12..offset].iter().all(|b| *b == 0);
+            Some((
+                pattern,
+                if padded {
+                    Encoding::Word
+                } else {
+                    Encoding::Packed
+                },
+            ))
+        } else if rest.starts_with(&pattern.lower_hex) || rest.starts_with(&pattern.checksum_hex) {
+            Some((pattern, Encoding::Hex))
+        } else {
+            None
+        }
+    })
+}
+
+fn to_lower_hex(address: &Address) -> Vec<u8> {
+    format!("{address:x}").into_bytes()
+}
+
+// Without the `0x` prefix, the text may omit it.
+fn to_checksum_hex(address: &Address) -> Vec<u8> {
+    to_checksum(address, None).as_bytes()[2..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressSubstitute, Encoding};
+    use ethers::abi;
+    use ethers::prelude::*;
+    use ethers::utils::to_checksum;
+
+    #[tokio::test]
+    async fn substitute_address() {
+        let sender = Address::random();
+        let origin = Address::random();
+        let contract = Address::random();
+        let token = Address::random();
+        let substitute = AddressSubstitute::default()
+            .with(sender, contract)
+            .with(origin, contract);
+
+        // swap(uint256 amount, bytes path, address recipient, string memo), the path is packed.
+        let path = [token.as_bytes(), &[0, 0x0b, 0xb8], sender.as_bytes()].concat();
+        let memo = format!("refund to {}", to_checksum(&origin, None));
+        let mut data = vec![0x12, 0x34, 0x56, 0x78];
+        data.extend(abi::encode(&[
+            abi::Token::Uint(U256::exp10(18)),
+            abi::Token::Bytes(path.clone()),
+            abi::Token::Address(origin),
+            abi::Token::String(memo.clone()),
+        ]));
+
+        let (output, substitution_list) = substitute.run(&data);
+        assert_eq!(
+            substitution_list
+                .iter()
+                .map(|s| (s.from, s.encoding))
+                .collect::<Vec<_>>(),
+            vec![
+                (origin, Encoding::Word),
+                (sender, Encoding::Packed),
+                (origin, Encoding::Hex)
+            ]
+        );
+        let mut expected = vec![0x12, 0x34, 0x56, 0x78];
+        expected.extend(abi::encode(&[
+            abi::Token::Uint(U256::exp10(18)),
+            abi::Token::Bytes([token.as_bytes(), &[0, 0x0b, 0xb8], contract.as_bytes()].concat()),
+            abi::Token::Address(contract),
+            abi::Token::String(format!("refund to {}", to_checksum(&contract, None))),
+        ]));
+        assert_eq!(output.to_vec(), expected);
+    }
+
+    #[tokio::test]
+    async fn substitute_nested_calldata() {
+        let sender = Address::random();
+        let contract = Address::random();
+        // multicall(bytes[]), the nested calldata isn't aligned to the outer words.
+        let inner = [
+            vec![0xa9, 0x05, 0x9c, 0xbb],
+            abi::encode(&[abi::Token::Address(sender), abi::Token::Uint(U256::one())]),
+        ]
+        .concat();
+        let data = abi::encode(&[abi::Token::Array(vec![abi::Token::Bytes(inner)])]);
+
+        let (output, substitution_list) = AddressSubstitute::default()
+            .with(sender, contract)
+            .run(&data);
+        assert_eq!(substitution_list.len(), 1);
+        assert_eq!(substitution_list[0].encoding, Encoding::Word);
+        assert_ne!(substitution_list[0].offset % 32, 12);
+        assert_eq!(
+            output[substitution_list[0].offset..substitution_list[0].offset + 20],
+            *contract.as_bytes()
+        );
+
+        // The callee itself is kept.
+        let (output, substitution_list) = AddressSubstitute::default()
+            .with(sender, contract)
+            .except(sender)
+            .run(&data);
+        assert!(substitution_list.is_empty());
+        assert_eq!(output.to_vec(), data);
+    }
+}
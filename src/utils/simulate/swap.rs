@@ -0,0 +1,193 @@
+use super::state::token::TokenGain;
+use crate::utils::{ArbitrageContract, ArbitrageError, PriceOracle, SwapRouter};
+use cfmms::pool::Pool;
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// The min amount out is short of the oracle quote by this in basis points.
+// The swap reverts if the pool moves more, and so does the balance check.
+const SLIPPAGE_BPS: u64 = 50;
+
+abigen!(
+    IWrappedNative,
+    r#"[
+        function withdraw(uint256 wad) external
+    ]"#;
+);
+
+// `Arbitrage._arbitrage` only checks the native token balance, so switch the token profit to
+// native token at the end of the multicall: unwrap the wrapped native token directly,
+// swap other tokens by the router of the pool kind that the oracle prices them with,
+// through `Arbitrage.swapToNative` called by the contract itself.
+// The contract swaps its balance at execution up to the traced gain, e.g. less of the fee-on-transfer token,
+// the min amount out is the oracle quote less the slippage.
+// The token without a pool or a router is skipped, the balance check decides.
+// @param block The simulated block to price the token at
+pub async fn to_native_tx_list<M: Middleware + 'static, S: Signer + 'static>(
+    oracle: &PriceOracle<M, S>,
+    client: Arc<SignerMiddleware<M, S>>,
+    contract: Address,
+    router: SwapRouter,
+    wrapped_native: Address,
+    gains: &[TokenGain],
+    block: U64,
+) -> Result<Vec<TransactionRequest>, ArbitrageError> {
+    let mut amounts = BTreeMap::new();
+    for gain in gains {
+        *amounts.entry(gain.token).or_insert_with(U256::zero) += gain.amount;
+    }
+
+    let from = client.address();
+    let contract = ArbitrageContract::new(contract, client.clone());
+    let mut tx_list = Vec::new();
+    for (token, amount) in amounts {
+        if token == wrapped_native {
+            let call = IWrappedNative::new(token, client.clone()).withdraw(amount);
+            tx_list.push(to_tx(from, token, call.calldata()));
+            continue;
+        }
+
+        let (pool, amount_out) = match (
+            oracle.native_pool(token, block).await?,
+            oracle.to_native(token, amount, block).await?,
+        ) {
+            (Some(pool), Some(amount_out)) => (pool, amount_out),
+            _ => continue,
+        };
+        let amount_out_min = amount_out * (10_000 - SLIPPAGE_BPS) / 10_000;
+        if amount_out_min.is_zero() {
+            continue;
+        }
+        if let Some(data) = to_swap_call(
+            &contract,
+            router,
+            wrapped_native,
+            &pool,
+            token,
+            amount,
+            amount_out_min,
+        ) {
+            tx_list.push(to_tx(from, contract.address(), Some(data)));
+        }
+    }
+
+    Ok(tx_list)
+}
+
+// The self call of the contract swapping `token` up to `amount` by the router of the pool kind.
+// @return None if the chain has no router of the kind
+fn to_swap_call<M: Middleware>(
+    contract: &ArbitrageContract<M>,
+    router: SwapRouter,
+    wrapped_native: Address,
+    pool: &Pool,
+    token: Address,
+    amount: U256,
+    amount_out_min: U256,
+) -> Option<Bytes> {
+    match pool {
+        Pool::UniswapV2(_) => contract
+            .swap_to_native(
+                router.v2?,
+                vec![token, wrapped_native],
+                amount,
+                amount_out_min,
+            )
+            .calldata(),
+        Pool::UniswapV3(pool) => {
+            // abi.encodePacked(token, uint24 fee, wrapped native)
+            let path = [
+                token.as_bytes(),
+                &pool.fee.to_be_bytes()[1..],
+                wrapped_native.as_bytes(),
+            ]
+            .concat();
+            contract
+                .swap_to_native_by_exact_input(router.v3?, path.into(), amount, amount_out_min)
+                .calldata()
+        }
+    }
+}
+
+fn to_tx(from: Address, to: Address, data: Option<Bytes>) -> TransactionRequest {
+    TransactionRequest::new()
+        .from(from)
+        .to(to)
+        .value(U256::zero())
+        .data(data.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_swap_call;
+    use crate::utils::{
+        ArbitrageContract, SwapRouter, SwapToNativeByExactInputCall, SwapToNativeCall,
+    };
+    use cfmms::pool::{Pool, UniswapV2Pool, UniswapV3Pool};
+    use ethers::{abi::AbiDecode, prelude::*};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn swap_by_router_of_pool() {
+        let client = Arc::new(Provider::<Http>::try_from("http://localhost:8545").unwrap());
+        let contract = ArbitrageContract::new(Address::random(), client);
+        let router = SwapRouter {
+            v2: Some(Address::random()),
+            v3: Some(Address::random()),
+        };
+        let (token, wrapped_native) = (Address::random(), Address::random());
+        let (amount, amount_out_min) = (U256::from(1000), U256::from(1990));
+
+        let v2_pool = Pool::UniswapV2(UniswapV2Pool::default());
+        let data = to_swap_call(
+            &contract,
+            router,
+            wrapped_native,
+            &v2_pool,
+            token,
+            amount,
+            amount_out_min,
+        )
+        .unwrap();
+        let call = SwapToNativeCall::decode(data).unwrap();
+        assert_eq!(call.router, router.v2.unwrap());
+        assert_eq!(call.path, vec![token, wrapped_native]);
+        assert_eq!(call.max_amount_in, amount);
+        assert_eq!(call.amount_out_min, amount_out_min);
+
+        let v3_pool = Pool::UniswapV3(UniswapV3Pool {
+            fee: 3000,
+            ..Default::default()
+        });
+        let data = to_swap_call(
+            &contract,
+            router,
+            wrapped_native,
+            &v3_pool,
+            token,
+            amount,
+            amount_out_min,
+        )
+        .unwrap();
+        let call = SwapToNativeByExactInputCall::decode(data).unwrap();
+        assert_eq!(call.router, router.v3.unwrap());
+        assert_eq!(&call.path[..20], token.as_bytes());
+        assert_eq!(&call.path[20..23], &[0x00, 0x0b, 0xb8]);
+        assert_eq!(&call.path[23..], wrapped_native.as_bytes());
+        assert_eq!(call.amount_out_min, amount_out_min);
+
+        // No v3 router on the chain.
+        let router = SwapRouter { v3: None, ..router };
+        assert!(to_swap_call(
+            &contract,
+            router,
+            wrapped_native,
+            &v3_pool,
+            token,
+            amount,
+            amount_out_min
+        )
+        .is_none());
+    }
+}
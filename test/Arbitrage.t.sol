@@ -50,6 +50,56 @@ contract ArbitrageTest is Test {
         arbitrage.run_no_check(bytes(""));
     }
 
+    function testCannotRunFrontByNotOwner() public TestCannotOperateByNotOwner {
+        arbitrage.run_front(bytes(""));
+    }
+
+    function testCannotRunBackByNotOwner() public TestCannotOperateByNotOwner {
+        arbitrage.run_back(bytes(""));
+    }
+
+    function testSandwichByOwner() public {
+        vm.deal(address(arbitrage), 1 ether);
+        arbitrage.run_front(abi.encode(bytes32(""), 0, new bytes[](0)));
+        // the income of the whole sequence
+        vm.deal(address(arbitrage), 2 ether);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+        assertEq(address(arbitrage).balance, 2 ether);
+    }
+
+    function testCannotSandwichBySufficientIncome() public {
+        vm.deal(address(arbitrage), 1 ether);
+        arbitrage.run_front(abi.encode(bytes32(""), 0, new bytes[](0)));
+        // the loss of the whole sequence
+        vm.deal(address(arbitrage), 0.5 ether);
+        vm.expectRevert(SufficientIncome.selector);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+    }
+
+    function testCannotRunBackWithoutFront() public {
+        vm.deal(address(arbitrage), 1 ether);
+        vm.expectRevert(MissingCheckpoint.selector);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+    }
+
+    function testCannotRunBackByStaleCheckpoint() public {
+        vm.deal(address(arbitrage), 1 ether);
+        arbitrage.run_front(abi.encode(bytes32(""), 0, new bytes[](0)));
+        vm.roll(block.number + 1);
+        vm.deal(address(arbitrage), 2 ether);
+        vm.expectRevert(MissingCheckpoint.selector);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+    }
+
+    function testCannotRunBackTwice() public {
+        vm.deal(address(arbitrage), 1 ether);
+        arbitrage.run_front(abi.encode(bytes32(""), 0, new bytes[](0)));
+        vm.deal(address(arbitrage), 2 ether);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+        vm.expectRevert(MissingCheckpoint.selector);
+        arbitrage.run_back(abi.encode(bytes32(""), 0, new bytes[](0)));
+    }
+
     function testFlashArbitrageByOwner() public {
         uint256 _lenderAmount = 1000 ether;
 
@@ -73,6 +123,60 @@ contract ArbitrageTest is Test {
         assertEq(fakeERC20.balanceOf(address(arbitrage)), 0 ether);
     }
 
+    function testCannotSwapToNativeByNotSelf() public {
+        vm.expectRevert(SelfCall.selector);
+        arbitrage.swapToNative(address(0), new address[](0), 0, 0);
+        vm.expectRevert(SelfCall.selector);
+        arbitrage.swapToNativeByExactInput(address(0), bytes(""), 0, 0);
+    }
+
+    // Only 1 ether of the token is received, e.g. the fee-on-transfer token, the min amount out is scaled with it.
+    function testSwapToNativeByBalance() public {
+        (FakeERC20 _token, FakeSwapRouter _router) = _setUpSwap();
+        address[] memory _path = new address[](2);
+        _path[0] = address(_token);
+        _path[1] = address(fakeERC20);
+
+        arbitrage.run(
+            _toSelfCall(
+                abi.encodeWithSelector(Arbitrage.swapToNative.selector, address(_router), _path, 2 ether, 2 ether)
+            )
+        );
+        assertEq(address(arbitrage).balance, 1 ether);
+        assertEq(_token.balanceOf(address(arbitrage)), 0);
+    }
+
+    function testSwapToNativeByExactInput() public {
+        (FakeERC20 _token, FakeSwapRouter _router) = _setUpSwap();
+        bytes memory _path = abi.encodePacked(address(_token), uint24(3000), address(fakeERC20));
+
+        arbitrage.run(
+            _toSelfCall(
+                abi.encodeWithSelector(
+                    Arbitrage.swapToNativeByExactInput.selector, address(_router), _path, 2 ether, 2 ether
+                )
+            )
+        );
+        assertEq(address(arbitrage).balance, 1 ether);
+        assertEq(_token.balanceOf(address(arbitrage)), 0);
+        assertEq(fakeERC20.balanceOf(address(arbitrage)), 0);
+    }
+
+    // The swap below the min amount out fails in the multicall, nothing is earned.
+    function testCannotSwapToNativeBySlippage() public {
+        (FakeERC20 _token, FakeSwapRouter _router) = _setUpSwap();
+        address[] memory _path = new address[](2);
+        _path[0] = address(_token);
+        _path[1] = address(fakeERC20);
+
+        vm.expectRevert(SufficientIncome.selector);
+        arbitrage.run(
+            _toSelfCall(
+                abi.encodeWithSelector(Arbitrage.swapToNative.selector, address(_router), _path, 1 ether, 2 ether)
+            )
+        );
+    }
+
     function testSetFlashLenderByOwner() public {
         arbitrage.setFlashLender(address(0));
     }
@@ -106,6 +210,50 @@ contract ArbitrageTest is Test {
     }
 
     receive() external payable {}
+
+    // The contract holds 1 ether of a token, the router swaps it 1:1.
+    function _setUpSwap() internal returns (FakeERC20 _token, FakeSwapRouter _router) {
+        _token = new FakeERC20("FakeToken", "Token", 18);
+        _router = new FakeSwapRouter(fakeERC20);
+        deal(address(_token), address(arbitrage), 1 ether);
+        vm.deal(address(_router), 10 ether);
+        vm.prank(address(_router));
+        fakeERC20.deposit{value: 5 ether}();
+    }
+
+    function _toSelfCall(bytes memory data) internal view returns (bytes memory) {
+        bytes[] memory payloads = new bytes[](1);
+        payloads[0] = abi.encode(address(arbitrage), 0, data);
+        return abi.encode(bytes32(""), 0, payloads);
+    }
+}
+
+// Swap 1:1 to ETH by the v2 style, or to WETH by the v3 style.
+contract FakeSwapRouter is IUniswapV2Router, IUniswapV3Router {
+    FakeERC20 weth;
+
+    constructor(FakeERC20 _weth) {
+        weth = _weth;
+    }
+
+    function swapExactTokensForETHSupportingFeeOnTransferTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 /* deadline */
+    ) external {
+        require(amountIn >= amountOutMin, "UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT");
+        IERC20(path[0]).transferFrom(msg.sender, address(this), amountIn);
+        payable(to).transfer(amountIn);
+    }
+
+    function exactInput(ExactInputParams calldata params) external payable returns (uint256) {
+        require(params.amountIn >= params.amountOutMinimum, "Too little received");
+        IERC20(address(bytes20(params.path[:20]))).transferFrom(msg.sender, address(this), params.amountIn);
+        weth.transfer(params.recipient, params.amountIn);
+        return params.amountIn;
+    }
 }
 
 contract FakeERC20 is ERC20, Owned {
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: AGPL-3.0-only
+pragma solidity ^0.8.13;
+
+import "forge-std/Test.sol";
+import "contract/Arbitrage.sol";
+import "contract/BalancerFlashLender.sol";
+
+contract BalancerFlashLenderTest is Test {
+    Arbitrage public arbitrage;
+    FakeWETH public fakeWETH;
+    FakeVault public vault;
+    BalancerFlashLender public flashLender;
+
+    function setUp() public {
+        arbitrage = new Arbitrage();
+        fakeWETH = new FakeWETH();
+        vault = new FakeVault();
+        flashLender = new BalancerFlashLender(IBalancerVault(address(vault)));
+
+        vm.deal(address(vault), 1000 ether);
+        vm.prank(address(vault));
+        fakeWETH.deposit{value: 1000 ether}();
+        arbitrage.setFlashLender(address(flashLender));
+    }
+
+    function testMaxFlashLoanAndFee() public {
+        assertEq(flashLender.maxFlashLoan(address(fakeWETH)), 1000 ether);
+        assertEq(flashLender.flashFee(address(fakeWETH), 1000 ether), 0);
+        // 0.1%
+        vault.setFlashLoanFeePercentage(1e15);
+        assertEq(flashLender.flashFee(address(fakeWETH), 1000 ether), 1 ether);
+        assertEq(flashLender.flashFee(address(fakeWETH), 1), 1);
+    }
+
+    // Borrow the token other than DAI through `Arbitrage.run`, the same as the bundle tx.
+    function testFlashArbitrageByOwner() public {
+        arbitrage.run(_toFlashArbitrage(1000 ether));
+        assertEq(address(arbitrage).balance, 1 ether);
+        assertEq(fakeWETH.balanceOf(address(arbitrage)), 1 ether);
+        assertEq(fakeWETH.balanceOf(address(vault)), 1000 ether);
+    }
+
+    function testCannotFlashArbitrageWithoutRepay() public {
+        // The 10 ether fee isn't covered by the income in token, the failed loan earns nothing.
+        vault.setFlashLoanFeePercentage(1e16);
+        vm.expectRevert(SufficientIncome.selector);
+        arbitrage.run(_toFlashArbitrage(1000 ether));
+    }
+
+    // The nested loan of the second token reenters the vault and fails in the multicall, nothing is earned.
+    function testCannotNestFlashLoan() public {
+        FakeWETH _otherToken = new FakeWETH();
+        vm.deal(address(vault), 1000 ether);
+        vm.prank(address(vault));
+        _otherToken.deposit{value: 1000 ether}();
+
+        bytes[] memory loan = new bytes[](1);
+        loan[0] = abi.encode(
+            address(flashLender),
+            0,
+            abi.encodeWithSelector(
+                IERC3156FlashLender.flashLoan.selector,
+                address(arbitrage),
+                address(_otherToken),
+                1 ether,
+                _toFlashArbitrage(1000 ether)
+            )
+        );
+
+        vm.expectRevert(SufficientIncome.selector);
+        arbitrage.run(abi.encode(bytes32(""), 0, loan));
+    }
+
+    function testCannotReceiveFlashLoanByNotVault() public {
+        vm.expectRevert(VaultCall.selector);
+        flashLender.receiveFlashLoan(new IERC20[](0), new uint256[](0), new uint256[](0), bytes(""));
+    }
+
+    // Anyone can call the vault with the lender as the recipient, the borrower mustn't be called back.
+    function testCannotReceiveUnknownLoan() public {
+        IERC20[] memory _tokens = new IERC20[](1);
+        _tokens[0] = IERC20(address(fakeWETH));
+        uint256[] memory _amounts = new uint256[](1);
+        _amounts[0] = 1 ether;
+
+        vm.expectRevert(UnknownLoan.selector);
+        vault.flashLoan(
+            flashLender, _tokens, _amounts, abi.encode(address(this), address(arbitrage), abi.encode(bytes32(""), 0))
+        );
+    }
+
+    // The data of `Arbitrage.run` borrowing `amount`, it earns 2 ether in token and withdraws 1 ether.
+    function _toFlashArbitrage(uint256 amount) internal view returns (bytes memory) {
+        bytes[] memory payloads = new bytes[](2);
+        payloads[0] = abi.encode(address(fakeWETH), 0, abi.encodeWithSignature("exploit()", ""));
+        payloads[1] = abi.encode(address(fakeWETH), 0, abi.encodeWithSignature("withdraw(uint256)", 1 ether));
+        bytes[] memory loan = new bytes[](1);
+        loan[0] = abi.encode(
+            address(flashLender),
+            0,
+            abi.encodeWithSelector(
+                IERC3156FlashLender.flashLoan.selector,
+                address(arbitrage),
+                address(fakeWETH),
+                amount,
+                abi.encode(bytes32(""), 0, payloads)
+            )
+        );
+        return abi.encode(bytes32(""), 0, loan);
+    }
+}
+
+contract FakeWETH is ERC20 {
+    constructor() ERC20("Wrapped Ether", "WETH", 18) {}
+
+    function exploit() external {
+        if (balanceOf[msg.sender] >= 1000 ether) {
+            _mint(msg.sender, 2 ether);
+        }
+    }
+
+    function deposit() external payable {
+        _mint(msg.sender, msg.value);
+    }
+
+    function withdraw(uint256 amount) external {
+        _burn(msg.sender, amount);
+        payable(msg.sender).transfer(amount);
+    }
+}
+
+// The flash loan of the Balancer vault, the balance is checked after the callback.
+contract FakeVault is IBalancerVault, IProtocolFeesCollector {
+    uint256 flashLoanFeePercentage;
+    bool entered;
+
+    function setFlashLoanFeePercentage(uint256 _percentage) external {
+        flashLoanFeePercentage = _percentage;
+    }
+
+    function getProtocolFeesCollector() external view returns (IProtocolFeesCollector) {
+        return this;
+    }
+
+    function getFlashLoanFeePercentage() external view returns (uint256) {
+        return flashLoanFeePercentage;
+    }
+
+    function flashLoan(
+        IFlashLoanRecipient recipient,
+        IERC20[] memory tokens,
+        uint256[] memory amounts,
+        bytes memory userData
+    ) external {
+        // nonReentrant
+        require(!entered, "BAL#400");
+        entered = true;
+        uint256[] memory _feeAmounts = new uint256[](tokens.length);
+        uint256[] memory _balances = new uint256[](tokens.length);
+        for (uint256 i = 0; i < tokens.length; i++) {
+            _feeAmounts[i] = (amounts[i] * flashLoanFeePercentage + 1e18 - 1) / 1e18;
+            _balances[i] = tokens[i].balanceOf(address(this));
+            tokens[i].transfer(address(recipient), amounts[i]);
+        }
+
+        recipient.receiveFlashLoan(tokens, amounts, _feeAmounts, userData);
+
+        for (uint256 i = 0; i < tokens.length; i++) {
+            require(tokens[i].balanceOf(address(this)) >= _balances[i] + _feeAmounts[i], "BAL#602");
+        }
+        entered = false;
+    }
+}
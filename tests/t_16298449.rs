@@ -1,7 +1,7 @@
 use arbitrage::utils::*;
 use dotenv::dotenv;
 use ethers::{prelude::*, utils::Anvil};
-use std::cmp;
+use std::sync::Arc;
 
 #[tokio::test]
 async fn t_16298449() {
@@ -21,16 +21,17 @@ async fn t_16298449() {
     let wallet: LocalWallet = anvil.keys()[0].clone().into();
     let wallet = wallet.with_chain_id(CHAIN_ID);
     let anvil_provider = Provider::<Http>::connect(&anvil.endpoint()).await;
-    let anvil_client = SignerMiddleware::new(anvil_provider, wallet.clone());
-    let arbitrage = ArbitrageUtil::deploy(&anvil_client).await.unwrap();
+    let anvil_client = Arc::new(SignerMiddleware::new(anvil_provider, wallet.clone()));
+    let arbitrage = ArbitrageUtil::deploy(anvil_client.clone()).await.unwrap();
 
     let provider = Provider::<Http>::connect(HTTP_RPC_URL).await;
-    let client = SignerMiddleware::new(provider, wallet.clone());
-    let simulate = Simulate::init(&client, Some(arbitrage.address()))
+    let client = Arc::new(SignerMiddleware::new(provider, wallet.clone()));
+    let simulate = Simulate::init(client.clone(), Some(arbitrage.address()))
         .await
         .unwrap();
     let tx_hash = TX_HASH.parse::<TxHash>().unwrap();
     let (tx_queue, profit) = simulate.run(tx_hash, true).await.unwrap().unwrap();
+    let policy = ProfitShare::new(70).with_cap(U256::from(12365048376181357_u64));
     log_profit(
         &anvil_client,
         arbitrage.address(),
@@ -41,11 +42,7 @@ async fn t_16298449() {
                 // No test for flashbot, for more detail, see:
                 // https://github.com/foundry-rs/foundry/issues/2089
                 if let Ok(tx) = arbitrage
-                    .to_tx(
-                        tx_list,
-                        true,
-                        Some(cmp::min(U256::from(12365048376181357_u64), profit * 7 / 10)),
-                    )
+                    .to_tx(tx_list, true, policy.bribe(&BribeContext::new(profit)))
                     .await
                 {
                     let _ = anvil_client
@@ -1,6 +1,7 @@
 use arbitrage::utils::*;
 use dotenv::dotenv;
 use ethers::{prelude::*, utils::Anvil};
+use std::sync::Arc;
 
 #[tokio::test]
 async fn t_16384470() {
@@ -20,12 +21,12 @@ async fn t_16384470() {
     let wallet: LocalWallet = anvil.keys()[0].clone().into();
     let wallet = wallet.with_chain_id(CHAIN_ID);
     let anvil_provider = Provider::<Http>::connect(&anvil.endpoint()).await;
-    let anvil_client = SignerMiddleware::new(anvil_provider, wallet.clone());
-    let arbitrage = ArbitrageUtil::deploy(&anvil_client).await.unwrap();
+    let anvil_client = Arc::new(SignerMiddleware::new(anvil_provider, wallet.clone()));
+    let arbitrage = ArbitrageUtil::deploy(anvil_client.clone()).await.unwrap();
 
     let provider = Provider::<Http>::connect(HTTP_RPC_URL).await;
-    let client = SignerMiddleware::new(provider, wallet.clone());
-    let simulate = Simulate::init(&client, Some(arbitrage.address()))
+    let client = Arc::new(SignerMiddleware::new(provider, wallet.clone()));
+    let simulate = Simulate::init(client.clone(), Some(arbitrage.address()))
         .await
         .unwrap();
     let tx_hash = TX_HASH.parse::<TxHash>().unwrap();